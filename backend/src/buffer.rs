@@ -2,10 +2,9 @@ use std::collections::HashSet;
 use std::time::Duration;
 
 use anyhow::Result;
-use chrono::NaiveTime;
 use geo::{Area, BooleanOps, ConvexHull, Coord, LineString, MultiPolygon, Polygon};
 use geojson::{Feature, FeatureCollection, Geometry};
-use graph::{Graph, PathStep, ProfileID, Route};
+use graph::{Graph, PathStep, ProfileID, Route, ServiceTime};
 use rstar::RTreeObject;
 
 use crate::{MapModel, Zones};
@@ -15,7 +14,7 @@ impl MapModel {
         &self,
         routes: Vec<Route>,
         profile: ProfileID,
-        start_time: NaiveTime,
+        start_time: ServiceTime,
         limit: Duration,
     ) -> Result<String> {
         let mut features = Vec::new();
@@ -38,11 +37,11 @@ impl MapModel {
             }
         }
 
-        let public_transit = false; // TODO
+        let transit = None; // TODO
         let cost_per_road = self.graph.get_costs(
             starts.into_iter().collect(),
             profile,
-            public_transit,
+            transit,
             start_time,
             start_time + limit,
         );
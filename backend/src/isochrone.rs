@@ -1,10 +1,10 @@
+use std::collections::HashMap;
 use std::time::Duration;
 
 use anyhow::Result;
-use chrono::NaiveTime;
 use geo::{line_measures::Densifiable, Coord, Euclidean, Rect};
 use geojson::{Feature, Geometry};
-use graph::{Graph, ProfileID, Timer};
+use graph::{Graph, ProfileID, RoadID, ServiceTime, Timer, TransitOptions};
 use serde::Deserialize;
 use utils::Grid;
 
@@ -22,17 +22,43 @@ pub enum Source {
     FromAmenities(Vec<String>),
 }
 
+/// How long it takes to reach a road, over every departure time. Departures that don't reach the
+/// road count as taking forever, so `median` and `p90` are `None` if too few departures reach it.
+struct Costs {
+    min: Duration,
+    median: Option<Duration>,
+    p90: Option<Duration>,
+}
+
+impl Costs {
+    fn new(mut per_departure: Vec<Option<Duration>>) -> Option<Self> {
+        per_departure.sort_by_key(|x| x.unwrap_or(Duration::MAX));
+        let n = per_departure.len();
+        let percentile = |p: f64| per_departure[((p * n as f64).ceil() as usize).clamp(1, n) - 1];
+        Some(Self {
+            min: per_departure.first().copied()??,
+            median: percentile(0.5),
+            p90: percentile(0.9),
+        })
+    }
+}
+
+/// If there are multiple `departures`, the min, median, and 90th percentile time to reach each
+/// road or grid cell is calculated.
 pub fn calculate(
     graph: &Graph,
     amenities: &Amenities,
     source: Source,
     profile: ProfileID,
     style: Style,
-    public_transit: bool,
-    start_time: NaiveTime,
+    transit: Option<TransitOptions>,
+    departures: Vec<ServiceTime>,
     limit: Duration,
     mut timer: Timer,
 ) -> Result<String> {
+    if departures.is_empty() {
+        bail!("No departure times");
+    }
     let mut starts = Vec::new();
     match source {
         Source::Single(pt) => {
@@ -59,13 +85,11 @@ pub fn calculate(
     }
 
     timer.step("get_costs");
-    let cost_per_road = graph.get_costs(
-        starts,
-        profile,
-        public_transit,
-        start_time,
-        start_time + limit,
-    );
+    let cost_per_road: HashMap<RoadID, Costs> = graph
+        .get_costs_range(starts, profile, transit.as_ref(), &departures, limit)
+        .into_iter()
+        .filter_map(|(r, per_departure)| Some((r, Costs::new(per_departure)?)))
+        .collect();
     timer.push("render to GJ");
 
     // Show reached amenities
@@ -82,18 +106,22 @@ pub fn calculate(
                 let mut f = Feature::from(Geometry::from(
                     &graph.mercator.to_wgs84(&graph.roads[r.0].linestring),
                 ));
-                f.set_property("cost_seconds", cost.as_secs());
+                f.set_property("cost_seconds", cost.min.as_secs());
+                f.set_property("median_seconds", cost.median.map(|x| x.as_secs()));
+                f.set_property("p90_seconds", cost.p90.map(|x| x.as_secs()));
                 features.push(f);
             }
         }
         Style::Grid | Style::Contours => {
             timer.step("make grid");
-            // Grid values are cost in seconds
-            let mut grid: Grid<f64> = Grid::new(
+            // Grid values are cost in seconds, with `None` meaning unreached
+            let mut grid: Grid<Option<f64>> = Grid::new(
                 (graph.mercator.width / RESOLUTION_M).ceil() as usize,
                 (graph.mercator.height / RESOLUTION_M).ceil() as usize,
-                0.0,
+                None,
             );
+            let mut median_grid = Grid::new(grid.width, grid.height, None);
+            let mut p90_grid = Grid::new(grid.width, grid.height, None);
 
             for (r, cost) in cost_per_road {
                 for pt in graph.roads[r.0]
@@ -107,12 +135,14 @@ pub fn calculate(
                     );
                     // If there are overlapping grid cells (bridges, tunnels, precision), just blindly
                     // clobber
-                    grid.data[grid_idx] = cost.as_secs_f64();
+                    grid.data[grid_idx] = Some(cost.min.as_secs_f64());
+                    median_grid.data[grid_idx] = cost.median.map(|x| x.as_secs_f64());
+                    p90_grid.data[grid_idx] = cost.p90.map(|x| x.as_secs_f64());
                 }
             }
 
             if matches!(style, Style::Grid) {
-                features.extend(render_grid(graph, grid));
+                features.extend(render_grid(graph, grid, median_grid, p90_grid));
             } else {
                 features.extend(render_contours(graph, grid));
             }
@@ -129,15 +159,17 @@ pub fn calculate(
 
 const RESOLUTION_M: f64 = 100.0;
 
-fn render_contours(graph: &Graph, grid: Grid<f64>) -> Vec<Feature> {
+fn render_contours(graph: &Graph, grid: Grid<Option<f64>>) -> Vec<Feature> {
     let smooth = false;
     let contour_builder = contour::ContourBuilder::new(grid.width, grid.height, smooth)
         .x_step(RESOLUTION_M)
         .y_step(RESOLUTION_M);
     let thresholds = vec![3. * 60., 6. * 60., 9. * 60., 12. * 60., 15. * 60.];
+    // Unreached cells go below the lowest threshold, so they're in no band
+    let values: Vec<f64> = grid.data.iter().map(|x| x.unwrap_or(0.0)).collect();
 
     let mut features = Vec::new();
-    for band in contour_builder.isobands(&grid.data, &thresholds).unwrap() {
+    for band in contour_builder.isobands(&values, &thresholds).unwrap() {
         let mut f = Feature::from(Geometry::from(&graph.mercator.to_wgs84(band.geometry())));
         f.set_property("min_seconds", band.min_v());
         f.set_property("max_seconds", band.max_v());
@@ -146,14 +178,18 @@ fn render_contours(graph: &Graph, grid: Grid<f64>) -> Vec<Feature> {
     features
 }
 
-fn render_grid(graph: &Graph, grid: Grid<f64>) -> Vec<Feature> {
+fn render_grid(
+    graph: &Graph,
+    grid: Grid<Option<f64>>,
+    median_grid: Grid<Option<f64>>,
+    p90_grid: Grid<Option<f64>>,
+) -> Vec<Feature> {
     let mut features = Vec::new();
     for x in 0..grid.width {
         for y in 0..grid.height {
-            let value = grid.data[grid.idx(x, y)];
-            if value == 0.0 {
+            let Some(value) = grid.data[grid.idx(x, y)] else {
                 continue;
-            }
+            };
 
             let rect = Rect::new(
                 Coord {
@@ -171,6 +207,9 @@ fn render_grid(graph: &Graph, grid: Grid<f64>) -> Vec<Feature> {
             let min = step * (value / step).floor();
             f.set_property("min_seconds", min);
             f.set_property("max_seconds", min + step);
+            for (key, grid) in [("median_seconds", &median_grid), ("p90_seconds", &p90_grid)] {
+                f.set_property(key, grid.data[grid.idx(x, y)]);
+            }
             features.push(f);
         }
     }
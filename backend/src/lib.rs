@@ -5,14 +5,15 @@ use std::collections::HashSet;
 use std::sync::Once;
 use std::time::Duration;
 
-use chrono::NaiveTime;
+use chrono::NaiveDate;
 use geo::{Coord, LineString};
 use geojson::{de::deserialize_geometry, Feature, GeoJson, Geometry};
-use graph::{Graph, ProfileID, Timer};
+use graph::{Graph, ProfileID, ServiceTime, Timer, TransitMode, TransitOptions};
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 
 use crate::amenity::Amenities;
+pub use crate::zone::PopulationSource;
 use crate::zone::Zones;
 
 mod amenity;
@@ -23,6 +24,11 @@ mod zone;
 
 static START: Once = Once::new();
 
+// Keep transit stops just outside the study area, so people near the edge can use them. Stops
+// can only be reached if they snap to a road, and roads usually end at the boundary, so keeping
+// stops any further out than snapping reaches is pointless.
+const GTFS_BUFFER_METERS: f64 = graph::MAX_SNAP_DISTANCE;
+
 // TODO Rename
 #[wasm_bindgen]
 #[derive(Serialize, Deserialize)]
@@ -48,7 +54,37 @@ impl MapModel {
         });
 
         let mut timer = Timer::new("build graph", progress_cb);
-        let model = MapModel::create(input_bytes, gtfs_url, population_url, &mut timer)
+        let gtfs = gtfs_url
+            .map(graph::GtfsSource::Geomedea)
+            .into_iter()
+            .collect();
+        let population = population_url.map(PopulationSource::Url);
+        let model = MapModel::create(input_bytes, gtfs, None, population, &mut timer)
+            .await
+            .map_err(err_to_js)?;
+        timer.done();
+
+        Ok(model)
+    }
+
+    /// Like `new`, but takes the bytes of a zipped GTFS feed, like one the user picked locally
+    #[wasm_bindgen(js_name = withGtfsBytes)]
+    pub async fn with_gtfs_bytes(
+        input_bytes: &[u8],
+        gtfs_bytes: Vec<u8>,
+        population_url: Option<String>,
+        progress_cb: Option<js_sys::Function>,
+    ) -> Result<MapModel, JsValue> {
+        // Panics shouldn't happen, but if they do, console.log them.
+        console_error_panic_hook::set_once();
+        START.call_once(|| {
+            console_log::init_with_level(log::Level::Info).unwrap();
+        });
+
+        let mut timer = Timer::new("build graph", progress_cb);
+        let gtfs = vec![graph::GtfsSource::Bytes(gtfs_bytes)];
+        let population = population_url.map(PopulationSource::Url);
+        let model = MapModel::create(input_bytes, gtfs, None, population, &mut timer)
             .await
             .map_err(err_to_js)?;
         timer.done();
@@ -67,6 +103,16 @@ impl MapModel {
         bincode::deserialize_from(input_bytes).map_err(err_to_js)
     }
 
+    /// Applies delays, cancellations, and skipped stops from a GTFS-Realtime feed to the transit
+    /// timetable
+    #[wasm_bindgen(js_name = applyRealtime)]
+    pub fn apply_realtime(&mut self, input_bytes: &[u8]) -> Result<(), JsValue> {
+        self.graph
+            .gtfs
+            .apply_realtime(input_bytes)
+            .map_err(err_to_js)
+    }
+
     /// Returns a GeoJSON string. Just shows the full network
     #[wasm_bindgen(js_name = renderDebug)]
     pub fn render_debug(&self) -> Result<String, JsValue> {
@@ -126,20 +172,44 @@ impl MapModel {
             profile,
             // TODO Hack
             serde_json::from_str(&format!("\"{}\"", req.style)).map_err(err_to_js)?,
-            req.transit,
-            NaiveTime::parse_from_str(&req.start_time, "%H:%M").map_err(err_to_js)?,
+            if req.transit {
+                Some(self.transit_options(&req.transit_settings)?)
+            } else {
+                None
+            },
+            departure_window(&req.start_time, req.end_time.as_deref())?,
             Duration::from_secs(req.max_seconds),
             Timer::new("isochrone request", None),
         )
         .map_err(err_to_js)
     }
 
+    /// Returns roads with their PTAL access index and band
+    #[wasm_bindgen(js_name = ptal)]
+    pub fn ptal(&self, input: JsValue) -> Result<String, JsValue> {
+        let req: PtalRequest = serde_wasm_bindgen::from_value(input)?;
+        self.graph
+            .ptal_gj(
+                &self.transit_options(&req.transit_settings)?,
+                parse_time(&req.start_time)?,
+                parse_time(&req.end_time)?,
+            )
+            .map_err(err_to_js)
+    }
+
     #[wasm_bindgen(js_name = route)]
     pub fn route(&self, input: JsValue) -> Result<String, JsValue> {
         let req: RouteRequest = serde_wasm_bindgen::from_value(input)?;
         self.route_from_req(&req)
     }
 
+    /// Returns JSON with the fastest public transit journey for each number of transfers
+    #[wasm_bindgen(js_name = transitJourneys)]
+    pub fn transit_journeys(&self, input: JsValue) -> Result<String, JsValue> {
+        let req: JourneysRequest = serde_wasm_bindgen::from_value(input)?;
+        self.journeys_from_req(&req)
+    }
+
     #[wasm_bindgen(js_name = bufferRoute)]
     pub fn buffer_route(&self, input: JsValue) -> Result<String, JsValue> {
         let req: BufferRouteRequest = serde_wasm_bindgen::from_value(input)?;
@@ -168,7 +238,7 @@ impl MapModel {
                 .map_err(err_to_js)?
         };
 
-        let start_time = NaiveTime::parse_from_str(&req.start_time, "%H:%M").map_err(err_to_js)?;
+        let start_time = parse_time(&req.start_time)?;
         let limit = Duration::from_secs(req.max_seconds);
 
         self.buffer_routes(vec![route], profile, start_time, limit)
@@ -224,7 +294,7 @@ impl MapModel {
         }
 
         timer.step(format!("buffer {} routes", routes.len()));
-        let start_time = NaiveTime::parse_from_str(&req.start_time, "%H:%M").map_err(err_to_js)?;
+        let start_time = parse_time(&req.start_time)?;
         let limit = Duration::from_secs(req.max_seconds);
 
         let result = self
@@ -245,10 +315,35 @@ impl MapModel {
         }
     }
 
+    /// The date defaults to a representative weekday in the GTFS data.
+    fn transit_options(&self, settings: &TransitSettings) -> Result<TransitOptions, JsValue> {
+        let date = match settings.date {
+            Some(ref x) => NaiveDate::parse_from_str(x, "%Y-%m-%d").map_err(err_to_js)?,
+            None => self.graph.gtfs.default_date(),
+        };
+        let mut options = TransitOptions::new(date);
+        if let Some(seconds) = settings.min_transfer_seconds {
+            options.min_transfer_time = Duration::from_secs(seconds);
+        }
+        if let Some(ref modes) = settings.modes {
+            options.modes = Some(modes.iter().cloned().collect());
+        }
+        if settings.max_fare.is_some() {
+            // Fares can't be limited if they're missing, like from a geomedea file
+            self.graph.gtfs.fare_currency().map_err(err_to_js)?;
+        }
+        options.max_fare = settings.max_fare;
+        options.wheelchair = settings.wheelchair;
+        Ok(options)
+    }
+
+    /// If `gtfs_dates` are specified, only transit trips running on some day in that inclusive
+    /// range are kept
     pub async fn create(
         input_bytes: &[u8],
-        gtfs_url: Option<String>,
-        population_url: Option<String>,
+        gtfs: Vec<graph::GtfsSource>,
+        gtfs_dates: Option<(NaiveDate, NaiveDate)>,
+        population: Option<PopulationSource>,
         timer: &mut Timer,
     ) -> anyhow::Result<MapModel> {
         let mut amenities = Amenities::new();
@@ -267,16 +362,15 @@ impl MapModel {
 
         graph
             .setup_gtfs(
-                match gtfs_url {
-                    Some(url) => graph::GtfsSource::Geomedea(url),
-                    None => graph::GtfsSource::None,
-                },
+                gtfs,
+                GTFS_BUFFER_METERS,
+                gtfs_dates,
                 graph.profile_names["foot"],
                 timer,
             )
             .await?;
         amenities.finalize(&graph, timer);
-        let zones = Zones::load(population_url, &graph.mercator, timer).await?;
+        let zones = Zones::load(population, &graph.mercator, timer).await?;
 
         Ok(MapModel {
             graph,
@@ -310,7 +404,8 @@ impl MapModel {
                     end,
                     req.debug_search,
                     req.use_heuristic,
-                    NaiveTime::parse_from_str(&req.start_time, "%H:%M").map_err(err_to_js)?,
+                    &self.transit_options(&req.transit_settings)?,
+                    parse_time(&req.start_time)?,
                     Timer::new("route request", None),
                 )
                 .map_err(err_to_js)
@@ -325,6 +420,37 @@ impl MapModel {
         }
     }
 
+    pub fn journeys_from_req(&self, req: &JourneysRequest) -> Result<String, JsValue> {
+        let Some(profile) = self.graph.walking_profile_for_transit else {
+            return Err(JsValue::from_str("public transit hasn't been set up"));
+        };
+        let start = self.graph.snap_to_road(
+            self.graph.mercator.pt_to_mercator(Coord {
+                x: req.x1,
+                y: req.y1,
+            }),
+            profile,
+        );
+        let end = self.graph.snap_to_road(
+            self.graph.mercator.pt_to_mercator(Coord {
+                x: req.x2,
+                y: req.y2,
+            }),
+            profile,
+        );
+        let journeys = self
+            .graph
+            .transit_journeys(
+                start,
+                end,
+                &self.transit_options(&req.transit_settings)?,
+                parse_time(&req.start_time)?,
+                req.max_transfers,
+            )
+            .map_err(err_to_js)?;
+        serde_json::to_string(&journeys).map_err(err_to_js)
+    }
+
     pub fn graph(&self) -> &Graph {
         &self.graph
     }
@@ -346,9 +472,23 @@ pub struct IsochroneRequest {
     transit: bool,
     style: String,
     start_time: String,
+    /// If specified, depart every minute from `start_time` until this time
+    #[serde(default)]
+    end_time: Option<String>,
+    #[serde(flatten)]
+    transit_settings: TransitSettings,
     max_seconds: u64,
 }
 
+#[derive(Deserialize)]
+pub struct PtalRequest {
+    /// TfL uses the morning peak, 08:15 to 09:15
+    start_time: String,
+    end_time: String,
+    #[serde(flatten)]
+    transit_settings: TransitSettings,
+}
+
 #[derive(Deserialize)]
 pub struct RouteRequest {
     pub x1: f64,
@@ -361,6 +501,41 @@ pub struct RouteRequest {
     pub debug_search: bool,
     pub use_heuristic: bool,
     pub start_time: String,
+    #[serde(flatten)]
+    pub transit_settings: TransitSettings,
+}
+
+#[derive(Deserialize)]
+pub struct JourneysRequest {
+    pub x1: f64,
+    pub y1: f64,
+    pub x2: f64,
+    pub y2: f64,
+    pub start_time: String,
+    pub max_transfers: usize,
+    #[serde(flatten)]
+    pub transit_settings: TransitSettings,
+}
+
+/// Optional settings only used for public transit
+#[derive(Deserialize)]
+pub struct TransitSettings {
+    /// YYYY-MM-DD
+    #[serde(default)]
+    pub date: Option<String>,
+    /// Overrides the default time needed to change between trips
+    #[serde(default)]
+    pub min_transfer_seconds: Option<u64>,
+    /// Only use routes with these modes. By default, everything is used.
+    #[serde(default)]
+    pub modes: Option<Vec<TransitMode>>,
+    /// Don't take trips making the total fare more than this, in the feed's currency. Fails if
+    /// the feed has no fares or mixes currencies.
+    #[serde(default)]
+    pub max_fare: Option<f64>,
+    /// Only use wheelchair-accessible trips, stops, and entrances
+    #[serde(default)]
+    pub wheelchair: bool,
 }
 
 #[derive(Deserialize)]
@@ -400,3 +575,26 @@ struct GeoJsonLineString {
 fn err_to_js<E: std::fmt::Display>(err: E) -> JsValue {
     JsValue::from_str(&err.to_string())
 }
+
+/// Parses a HH:MM time, like the CLI does
+fn parse_time(x: &str) -> Result<ServiceTime, JsValue> {
+    ServiceTime::parse_hh_mm(x).map_err(err_to_js)
+}
+
+fn departure_window(start: &str, end: Option<&str>) -> Result<Vec<ServiceTime>, JsValue> {
+    let start = parse_time(start)?;
+    let Some(end) = end else {
+        return Ok(vec![start]);
+    };
+    let end = parse_time(end)?;
+    if end < start {
+        return Err(JsValue::from_str("end_time is before start_time"));
+    }
+    let mut times = Vec::new();
+    let mut time = start;
+    while time <= end {
+        times.push(time);
+        time = time + Duration::from_secs(60);
+    }
+    Ok(times)
+}
@@ -2,8 +2,7 @@ use std::collections::{HashMap, HashSet};
 use std::time::Duration;
 
 use anyhow::Result;
-use chrono::NaiveTime;
-use graph::{Graph, ProfileID, RoadID, Timer};
+use graph::{Graph, ProfileID, RoadID, ServiceTime, Timer};
 
 use crate::Amenities;
 
@@ -18,7 +17,7 @@ pub fn calculate(
     mut timer: Timer,
 ) -> Result<String> {
     // Exact time doesn't matter
-    let start_time = NaiveTime::from_hms_opt(7, 0, 0).unwrap();
+    let start_time = ServiceTime::from_hms(7, 0, 0)?;
     let end_time = start_time + limit;
 
     // Per road, store one arbitrary point of parking
@@ -50,7 +49,7 @@ pub fn calculate(
                     .intersection,
             ],
             profile,
-            false,
+            None,
             start_time,
             end_time,
         );
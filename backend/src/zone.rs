@@ -1,5 +1,10 @@
+use std::io::{Read, Seek};
+
 use anyhow::Result;
-use flatgeobuf::{FeatureProperties, FgbFeature, GeozeroGeometry, HttpFgbReader};
+use flatgeobuf::{
+    FallibleStreamingIterator, FeatureProperties, FgbFeature, FgbReader, GeozeroGeometry,
+    HttpFgbReader,
+};
 use geo::{Area, MultiPolygon, Polygon};
 use geojson::{Feature, FeatureCollection, Geometry};
 use rstar::{primitives::GeomWithData, RTree};
@@ -14,6 +19,17 @@ pub struct Zones {
     pub rtree: RTree<GeomWithData<Polygon, ZoneID>>,
 }
 
+/// Where to read population zones from. This is a FlatGeobuf file with polygons and a `population`
+/// property.
+pub enum PopulationSource {
+    /// Only the parts covering the study area are downloaded
+    Url(String),
+    /// A path to a local file
+    Path(String),
+    /// The bytes of a file
+    Bytes(Vec<u8>),
+}
+
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct ZoneID(pub usize);
 
@@ -29,13 +45,22 @@ pub struct Zone {
 
 impl Zones {
     pub async fn load(
-        population_url: Option<String>,
+        population: Option<PopulationSource>,
         mercator: &Mercator,
         timer: &mut Timer,
     ) -> Result<Self> {
-        let zones = if let Some(url) = population_url {
+        let zones = if let Some(source) = population {
             timer.step("load population zones");
-            load_zones(url, mercator).await?
+            match source {
+                PopulationSource::Url(url) => load_zones(url, mercator).await?,
+                PopulationSource::Path(path) => read_zones(
+                    std::io::BufReader::new(std::fs::File::open(path)?),
+                    mercator,
+                )?,
+                PopulationSource::Bytes(bytes) => {
+                    read_zones(std::io::Cursor::new(bytes), mercator)?
+                }
+            }
         } else {
             Vec::new()
         };
@@ -90,22 +115,42 @@ async fn load_zones(url: String, mercator: &Mercator) -> Result<Vec<Zone>> {
 
     let mut zones = Vec::new();
     while let Some(feature) = fgb.next().await? {
-        // TODO Could intersect with boundary_polygon, but some extras nearby won't hurt anything
-        let mut geom = get_multipolygon(feature)?;
-        mercator.to_mercator_in_place(&mut geom);
-        let area_km2 = 1e-6 * geom.unsigned_area();
-        // TODO Re-encode as UInt
-        let population = feature.property::<i64>("population")?.try_into()?;
-
-        zones.push(Zone {
-            geom,
-            population,
-            area_km2,
-        });
+        zones.push(to_zone(feature, mercator)?);
+    }
+    Ok(zones)
+}
+
+fn read_zones<R: Read + Seek>(reader: R, mercator: &Mercator) -> Result<Vec<Zone>> {
+    let bbox = mercator.wgs84_bounds;
+    let mut fgb = FgbReader::open(reader)?.select_bbox(
+        bbox.min().x,
+        bbox.min().y,
+        bbox.max().x,
+        bbox.max().y,
+    )?;
+
+    let mut zones = Vec::new();
+    while let Some(feature) = fgb.next()? {
+        zones.push(to_zone(feature, mercator)?);
     }
     Ok(zones)
 }
 
+fn to_zone(feature: &FgbFeature, mercator: &Mercator) -> Result<Zone> {
+    // TODO Could intersect with boundary_polygon, but some extras nearby won't hurt anything
+    let mut geom = get_multipolygon(feature)?;
+    mercator.to_mercator_in_place(&mut geom);
+    let area_km2 = 1e-6 * geom.unsigned_area();
+    // TODO Re-encode as UInt
+    let population = feature.property::<i64>("population")?.try_into()?;
+
+    Ok(Zone {
+        geom,
+        population,
+        area_km2,
+    })
+}
+
 fn get_multipolygon(f: &FgbFeature) -> Result<MultiPolygon> {
     let mut p = geozero::geo_types::GeoWriter::new();
     f.process_geom(&mut p)?;
@@ -2,14 +2,19 @@ use std::fs::File;
 use std::io::BufWriter;
 use std::time::Duration;
 
-use anyhow::{bail, Result};
-use backend::MapModel;
-use chrono::NaiveTime;
+use anyhow::{anyhow, bail, Result};
+use backend::{MapModel, PopulationSource};
+use chrono::NaiveDate;
 use clap::{Parser, Subcommand};
-use geo::{Contains, Coord, Euclidean, Length, LineString, Point};
+use geo::{
+    Contains, Coord, Euclidean, GeometryCollection, Length, LineString, MultiPolygon, Point,
+};
 use geojson::{de::deserialize_geometry, Feature, GeoJson, Geometry};
-use graph::{Direction, Graph, GtfsModel, ProfileID, Route, Timer};
+use graph::{
+    Direction, Graph, GtfsModel, ProfileID, Route, ServiceTime, StudyArea, Timer, TransitOptions,
+};
 use serde::{Deserialize, Serialize};
+use utils::Mercator;
 
 #[derive(Parser)]
 struct Args {
@@ -21,9 +26,86 @@ struct Args {
 enum Command {
     BuildGraph {
         osm_path: String,
+
+        /// Paths to geomedea files from `build-gtfs`, or GTFS directories or .zip files. If none
+        /// are specified, the file from assets.od2net.org is downloaded.
+        #[arg(long)]
+        gtfs: Vec<String>,
+
+        /// Path to a FlatGeobuf file with population zones. If not specified, the file from
+        /// assets.od2net.org is downloaded.
+        #[arg(long)]
+        population: Option<String>,
+
+        /// Only keep transit trips running on some day in this inclusive range, given as two
+        /// YYYY-MM-DD dates
+        #[arg(long, num_args = 2, value_names = ["START", "END"])]
+        dates: Vec<String>,
     },
+    /// Combines one or more GTFS feeds into gtfs.gmd
     BuildGTFS {
-        gtfs_dir: String,
+        /// Paths to GTFS directories or .zip files
+        #[arg(required = true)]
+        gtfs_paths: Vec<String>,
+
+        /// Only keep transit trips running on some day in this inclusive range, given as two
+        /// YYYY-MM-DD dates
+        #[arg(long, num_args = 2, value_names = ["START", "END"])]
+        dates: Vec<String>,
+    },
+    /// Reports problems with GTFS input in gtfs_issues.json and gtfs_issues.geojson
+    ValidateGTFS {
+        /// Path to a GTFS directory or .zip file
+        gtfs_path: String,
+
+        /// Path to a .geojson file covering the study area. If specified, stops outside of it are
+        /// reported.
+        #[arg(long)]
+        boundary: Option<String>,
+
+        /// Also keep stops this far outside the boundary
+        #[arg(long, default_value_t = 0.0)]
+        buffer_meters: f64,
+    },
+    /// Writes the part of a GTFS feed within a boundary to a directory, as standard GTFS
+    ClipGTFS {
+        /// Path to a GTFS directory or .zip file
+        gtfs_path: String,
+
+        /// Path to a .geojson file covering the study area
+        #[arg(long)]
+        boundary: String,
+
+        /// Also keep stops this far outside the boundary
+        #[arg(long, default_value_t = 0.0)]
+        buffer_meters: f64,
+
+        /// The directory to write to
+        #[arg(long, default_value = "clipped_gtfs")]
+        output: String,
+
+        /// Only keep transit trips running on some day in this inclusive range, given as two
+        /// YYYY-MM-DD dates
+        #[arg(long, num_args = 2, value_names = ["START", "END"])]
+        dates: Vec<String>,
+    },
+    /// Calculates the PTAL of every road near public transit, writing ptal.geojson
+    Ptal {
+        /// Path to a model.bin file
+        #[arg(long)]
+        model: String,
+
+        /// YYYY-MM-DD. Defaults to a representative weekday in the GTFS data.
+        #[arg(long)]
+        date: Option<String>,
+
+        /// HH:MM, like the web app. The default is the morning peak TfL uses.
+        #[arg(long, default_value = "08:15")]
+        start_time: String,
+
+        /// HH:MM
+        #[arg(long, default_value = "09:15")]
+        end_time: String,
     },
     SnapTest {
         /// Path to a model.bin file
@@ -50,17 +132,38 @@ async fn main() -> Result<()> {
     let args = Args::parse();
 
     match args.command {
-        Command::BuildGraph { osm_path } => {
+        Command::BuildGraph {
+            osm_path,
+            gtfs,
+            population,
+            dates,
+        } => {
             let mut timer = Timer::new("build model", None);
             let osm_bytes = std::fs::read(&osm_path)?;
-            let model = MapModel::create(
-                &osm_bytes,
-                // TODO Hardcoded, or could we read from local files at least?
-                Some("https://assets.od2net.org/gtfs.gmd".to_string()),
-                Some("https://assets.od2net.org/population.fgb".to_string()),
-                &mut timer,
-            )
-            .await?;
+            let gtfs = if gtfs.is_empty() {
+                vec![graph::GtfsSource::Geomedea(
+                    "https://assets.od2net.org/gtfs.gmd".to_string(),
+                )]
+            } else {
+                gtfs.into_iter()
+                    .map(|path| {
+                        if path.ends_with(".gmd") {
+                            graph::GtfsSource::GeomedeaFile(path)
+                        } else {
+                            graph::GtfsSource::Path(path)
+                        }
+                    })
+                    .collect()
+            };
+            let population = Some(match population {
+                Some(path) => PopulationSource::Path(path),
+                None => {
+                    PopulationSource::Url("https://assets.od2net.org/population.fgb".to_string())
+                }
+            });
+            let model =
+                MapModel::create(&osm_bytes, gtfs, date_range(dates)?, population, &mut timer)
+                    .await?;
 
             timer.step("Writing");
             let writer = BufWriter::new(File::create("model.bin")?);
@@ -69,15 +172,68 @@ async fn main() -> Result<()> {
             timer.done();
             Ok(())
         }
-        Command::BuildGTFS { gtfs_dir } => {
+        Command::BuildGTFS { gtfs_paths, dates } => {
             let mut timer = Timer::new("build geomedea from gtfs", None);
-            timer.step("parse GTFS");
-            let model = GtfsModel::parse(&gtfs_dir, None)?;
+            let dates = date_range(dates)?;
+            let mut feeds = Vec::new();
+            for path in gtfs_paths {
+                timer.log(format!("Reading {path}"));
+                feeds.push(match dates {
+                    Some(dates) => GtfsModel::parse_between(&path, dates, &mut timer)?,
+                    None => GtfsModel::parse(&path, None, &mut timer)?,
+                });
+            }
+            let model = GtfsModel::merge(feeds);
             timer.step("turn into geomedea");
             model.to_geomedea("gtfs.gmd")?;
             timer.done();
             Ok(())
         }
+        Command::ValidateGTFS {
+            gtfs_path,
+            boundary,
+            buffer_meters,
+        } => validate_gtfs(gtfs_path, boundary, buffer_meters),
+        Command::ClipGTFS {
+            gtfs_path,
+            boundary,
+            buffer_meters,
+            output,
+            dates,
+        } => {
+            let mut timer = Timer::new("clip GTFS", None);
+            let area = read_study_area(&boundary, buffer_meters, date_range(dates)?)?;
+            let model = GtfsModel::parse(&gtfs_path, Some(&area), &mut timer)?;
+            timer.step(format!("write to {output}"));
+            model.write_gtfs(&output, Some(&area.mercator))?;
+            timer.done();
+            Ok(())
+        }
+        Command::Ptal {
+            model,
+            date,
+            start_time,
+            end_time,
+        } => {
+            let mut timer = Timer::new("calculate PTAL", None);
+            timer.step("load model");
+            let model: MapModel = bincode::deserialize(&fs_err::read(&model)?)?;
+            let graph = model.graph();
+            let date = match date {
+                Some(x) => parse_date(&x)?,
+                None => graph.gtfs.default_date(),
+            };
+
+            timer.step("calculate");
+            let gj = graph.ptal_gj(
+                &TransitOptions::new(date),
+                ServiceTime::parse_hh_mm(&start_time)?,
+                ServiceTime::parse_hh_mm(&end_time)?,
+            )?;
+            fs_err::write("ptal.geojson", gj)?;
+            timer.done();
+            Ok(())
+        }
         Command::SnapTest {
             model,
             routes,
@@ -86,6 +242,73 @@ async fn main() -> Result<()> {
     }
 }
 
+fn validate_gtfs(
+    gtfs_path: String,
+    boundary_path: Option<String>,
+    buffer_meters: f64,
+) -> Result<()> {
+    let mut timer = Timer::new("validate GTFS", None);
+    let area = boundary_path
+        .map(|path| read_study_area(&path, buffer_meters, None))
+        .transpose()?;
+
+    let (_, issues) = GtfsModel::parse_with_issues(&gtfs_path, area.as_ref(), &mut timer)?;
+    timer.done();
+
+    println!("Found {} issues", issues.len());
+    fs_err::write("gtfs_issues.json", serde_json::to_string_pretty(&issues)?)?;
+    let features: Vec<Feature> = issues.iter().filter_map(|issue| issue.to_gj()).collect();
+    fs_err::write(
+        "gtfs_issues.geojson",
+        serde_json::to_string(&GeoJson::from(features))?,
+    )?;
+    Ok(())
+}
+
+/// Reads the polygons from a .geojson file
+fn read_study_area(
+    path: &str,
+    buffer_meters: f64,
+    dates: Option<(NaiveDate, NaiveDate)>,
+) -> Result<StudyArea> {
+    let gj: GeoJson = fs_err::read_to_string(path)?.parse()?;
+    let collection = GeometryCollection::try_from(&gj)?;
+    let mut polygons = Vec::new();
+    for geom in &collection {
+        match geom {
+            geo::Geometry::Polygon(p) => polygons.push(p.clone()),
+            geo::Geometry::MultiPolygon(mp) => polygons.extend(mp.0.clone()),
+            _ => {}
+        }
+    }
+    if polygons.is_empty() {
+        bail!("{path} has no polygons");
+    }
+    let Some(mercator) = Mercator::from(collection) else {
+        bail!("{path} has no geometry");
+    };
+    let boundary = mercator.to_mercator(&MultiPolygon(polygons));
+    Ok(StudyArea::new(mercator, boundary, buffer_meters, dates))
+}
+
+/// `--dates` is either missing or has a start and end
+fn date_range(dates: Vec<String>) -> Result<Option<(NaiveDate, NaiveDate)>> {
+    let dates = dates
+        .iter()
+        .map(|x| parse_date(x))
+        .collect::<Result<Vec<_>>>()?;
+    match dates[..] {
+        [] => Ok(None),
+        [start, end] if start <= end => Ok(Some((start, end))),
+        _ => bail!("--dates needs a start date no later than the end date"),
+    }
+}
+
+/// Parses YYYY-MM-DD
+fn parse_date(x: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(x, "%Y-%m-%d").map_err(|err| anyhow!("Bad date {x}: {err}"))
+}
+
 fn snap_test(model_path: String, routes_path: String, limit: Duration) -> Result<()> {
     let mut timer = Timer::new("snap routes", None);
 
@@ -180,7 +403,7 @@ fn snap_test(model_path: String, routes_path: String, limit: Duration) -> Result
 
     let num_routes = routes.len();
     timer.step("buffer around routes");
-    let start_time = NaiveTime::from_hms_opt(7, 0, 0).unwrap();
+    let start_time = ServiceTime::from_hms(7, 0, 0)?;
     fs_err::write(
         "buffered.geojson",
         model.buffer_routes(routes, graph.profile_names["bicycle"], start_time, limit)?,
@@ -151,14 +151,21 @@ impl Graph {
             timestamp: graph.timestamp,
 
             gtfs: GtfsModel::empty(),
+            footpaths: Default::default(),
         })
     }
 
-    /// Adds in GTFS data to the current graph. This only makes sense to call once.
+    /// Adds in GTFS data to the current graph. Multiple feeds are merged together. Stops outside
+    /// the boundary are removed, unless they're within `buffer_meters` of it. Stops more than
+    /// `MAX_SNAP_DISTANCE` from any road are removed too, so a larger buffer only helps when the
+    /// roads extend past the boundary. If `dates` are specified, only trips running on some day in
+    /// that inclusive range are kept. This only makes sense to call once.
     #[cfg(feature = "gtfs")]
     pub async fn setup_gtfs(
         &mut self,
-        source: crate::GtfsSource,
+        sources: Vec<crate::GtfsSource>,
+        buffer_meters: f64,
+        dates: Option<(chrono::NaiveDate, chrono::NaiveDate)>,
         profile: ProfileID,
         timer: &mut Timer,
     ) -> Result<()> {
@@ -167,15 +174,28 @@ impl Graph {
         }
         self.walking_profile_for_transit = Some(profile);
 
-        use crate::GtfsSource;
+        use crate::{GtfsSource, StudyArea};
+        use geo::MultiPolygon;
 
         timer.push("setting up GTFS");
         timer.step("parse");
-        let mut gtfs = match source {
-            GtfsSource::Dir(path) => GtfsModel::parse(&path, Some(&self.mercator))?,
-            GtfsSource::Geomedea(url) => GtfsModel::from_geomedea(&url, &self.mercator).await?,
-            GtfsSource::None => GtfsModel::empty(),
-        };
+        let area = StudyArea::new(
+            self.mercator.clone(),
+            MultiPolygon(vec![self.boundary_polygon.clone()]),
+            buffer_meters,
+            dates,
+        );
+        let mut feeds = Vec::new();
+        for source in sources {
+            feeds.push(match source {
+                GtfsSource::Path(path) => GtfsModel::parse(&path, Some(&area), timer)?,
+                GtfsSource::Bytes(bytes) => GtfsModel::parse_bytes(bytes, Some(&area), timer)?,
+                GtfsSource::Geomedea(url) => GtfsModel::from_geomedea(&url, &area).await?,
+                GtfsSource::GeomedeaFile(path) => GtfsModel::from_geomedea_file(&path, &area)?,
+                GtfsSource::GeomedeaBytes(bytes) => GtfsModel::from_geomedea_bytes(&bytes, &area)?,
+            });
+        }
+        let mut gtfs = GtfsModel::merge(feeds);
         snap_stops(&mut self.roads, &mut gtfs, &self.routers[profile.0], timer);
         self.gtfs = gtfs;
         timer.pop();
@@ -183,6 +203,11 @@ impl Graph {
     }
 }
 
+/// Stops further than this from any walkable road are removed, in meters. Roads usually end at
+/// the boundary, so keeping stops further outside it than this isn't useful.
+#[cfg(feature = "gtfs")]
+pub const MAX_SNAP_DISTANCE: f64 = 200.0;
+
 #[cfg(feature = "gtfs")]
 fn snap_stops(
     roads: &mut Vec<Road>,
@@ -190,6 +215,9 @@ fn snap_stops(
     foot_router: &Router,
     timer: &mut Timer,
 ) {
+    use geo::Distance;
+    use std::collections::BTreeSet;
+
     if gtfs.stops.is_empty() {
         return;
     }
@@ -199,18 +227,67 @@ fn snap_stops(
         gtfs.stops.len()
     ));
     // TODO Make an iterator method that returns the IDs too
+    let mut unsnapped = BTreeSet::new();
     for (idx, stop) in gtfs.stops.iter_mut().enumerate() {
         let stop_id = crate::gtfs::StopID(idx);
-        if let Some(r) = foot_router
-            .closest_road
-            .nearest_neighbor(&stop.point.into())
-        {
-            // TODO Limit how far away we snap, or use the boundary polygon
-            roads[r.data.0].stops.push(stop_id);
-            stop.road = r.data;
-        } else {
-            // TODO Need to get rid of the stop
-            error!("{stop_id:?} didn't snap to any road");
+
+        // Walk through station entrances if possible, otherwise directly to the stop
+        let mut access_points = Vec::new();
+        if let Some(station) = stop.station {
+            for entrance in &gtfs.stations[station.0].entrances {
+                if let Some((_, enter, exit)) =
+                    entrance.platforms.iter().find(|(s, _, _)| *s == stop_id)
+                {
+                    let step_free = entrance.wheelchair_boarding == Some(true);
+                    access_points.push((entrance.point, *enter, *exit, step_free));
+                }
+            }
+        }
+        if access_points.is_empty() {
+            access_points.push((stop.point, Duration::ZERO, Duration::ZERO, true));
+        }
+
+        for (point, enter, exit, step_free) in access_points {
+            if let Some(r) = foot_router.closest_road.nearest_neighbor(&point.into())
+                && Euclidean.distance(&point, r.geom()) <= MAX_SNAP_DISTANCE
+            {
+                stop.access.push(crate::gtfs::StopAccess {
+                    position: roads[r.data.0].closest_position(point),
+                    enter,
+                    exit,
+                    step_free,
+                });
+            }
+        }
+        if stop.access.is_empty() {
+            unsnapped.insert(stop_id);
+        }
+    }
+
+    if !unsnapped.is_empty() {
+        warn!(
+            "Removing {} stops more than {MAX_SNAP_DISTANCE}m from any walkable road: {}",
+            unsnapped.len(),
+            unsnapped
+                .iter()
+                .map(|s| {
+                    let stop = &gtfs.stops[s.0];
+                    format!("{} ({:?})", stop.name, stop.orig_id)
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        timer.step(format!("remove {} unsnapped stops", unsnapped.len()));
+        gtfs.remove_stops(&unsnapped);
+    }
+
+    for (idx, stop) in gtfs.stops.iter().enumerate() {
+        let stop_id = crate::gtfs::StopID(idx);
+        for access in &stop.access {
+            let road = &mut roads[access.position.road.0];
+            if !road.stops.contains(&stop_id) {
+                road.stops.push(stop_id);
+            }
         }
     }
 }
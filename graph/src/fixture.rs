@@ -0,0 +1,111 @@
+//! Small graphs and GTFS feeds for tests
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use geo::{LineString, MultiPolygon, Point, Rect, coord};
+use utils::{Mercator, Tags};
+
+use crate::gtfs::{StopAccess, StopID};
+use crate::{
+    Direction, Graph, GtfsModel, Intersection, IntersectionID, Position, ProfileID, Road, RoadID,
+    Router, StudyArea, Timer,
+};
+
+/// The path to a GTFS feed in `tests/gtfs`
+pub fn gtfs_path(name: &str) -> String {
+    format!("{}/tests/gtfs/{name}", env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Covers every stop in the test feeds, but not the `outside` stops
+pub fn study_area() -> StudyArea {
+    let rect = Rect::new(coord! { x: -0.2, y: 51.4 }, coord! { x: 0.0, y: 51.6 });
+    let mercator = Mercator::from(rect).unwrap();
+    let boundary = mercator.to_mercator(&MultiPolygon(vec![rect.to_polygon()]));
+    StudyArea::new(mercator, boundary, 0.0, None)
+}
+
+/// Parses a feed from `tests/gtfs` within `study_area`
+pub fn gtfs(name: &str) -> GtfsModel {
+    GtfsModel::parse(
+        &gtfs_path(name),
+        Some(&study_area()),
+        &mut Timer::new("parse test feed", None),
+    )
+    .unwrap()
+}
+
+/// Puts a feed from `tests/gtfs` on a straight line of roads, one per stop in the order of
+/// stops.txt, with every stop halfway along its road. Walking along road `i` takes `road_mins[i]`
+/// minutes in either direction.
+pub fn graph(name: &str, road_mins: &[u64]) -> Graph {
+    let mut gtfs = gtfs(name);
+    assert_eq!(gtfs.stops.len(), road_mins.len());
+    let area = study_area();
+
+    let points: Vec<Point> = (0..=road_mins.len())
+        .map(|i| {
+            area.mercator
+                .to_mercator(&Point::new(-0.1 + 0.001 * i as f64, 51.5))
+        })
+        .collect();
+    let intersections: Vec<Intersection> = points
+        .iter()
+        .enumerate()
+        .map(|(i, point)| Intersection {
+            id: IntersectionID(i),
+            node: osm_reader::NodeID(i as i64),
+            point: *point,
+            roads: [i.checked_sub(1), (i < road_mins.len()).then_some(i)]
+                .into_iter()
+                .flatten()
+                .map(RoadID)
+                .collect(),
+        })
+        .collect();
+    let roads: Vec<Road> = road_mins
+        .iter()
+        .enumerate()
+        .map(|(i, mins)| Road {
+            id: RoadID(i),
+            src_i: IntersectionID(i),
+            dst_i: IntersectionID(i + 1),
+            way: osm_reader::WayID(i as i64),
+            node1: osm_reader::NodeID(i as i64),
+            node2: osm_reader::NodeID(i as i64 + 1),
+            osm_tags: Tags(BTreeMap::new()),
+            length_meters: 100.0,
+            linestring: LineString::from(vec![points[i], points[i + 1]]),
+            access: vec![Direction::Both],
+            cost: vec![Duration::from_secs(60 * mins)],
+            stops: vec![StopID(i)],
+        })
+        .collect();
+    let profile = ProfileID(0);
+    let routers = vec![Router::new(&roads, profile)];
+
+    for (i, stop) in gtfs.stops.iter_mut().enumerate() {
+        stop.access.push(StopAccess {
+            position: Position {
+                road: RoadID(i),
+                fraction_along: 0.5,
+                intersection: IntersectionID(i),
+            },
+            enter: Duration::ZERO,
+            exit: Duration::ZERO,
+            step_free: true,
+        });
+    }
+    Graph {
+        roads,
+        intersections,
+        boundary_polygon: area.boundary.0[0].clone(),
+        mercator: area.mercator,
+        profile_names: BTreeMap::from([("foot".to_string(), profile)]),
+        walking_profile_for_transit: Some(profile),
+        routers,
+        timestamp: None,
+        gtfs,
+        footpaths: Default::default(),
+    }
+}
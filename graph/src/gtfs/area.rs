@@ -0,0 +1,102 @@
+use chrono::NaiveDate;
+use geo::{BoundingRect, Contains, Coord, Distance, Euclidean, MultiPolygon, Point, Rect};
+use utils::Mercator;
+
+/// Where and when GTFS data is needed. Stops outside of this are skipped.
+pub struct StudyArea {
+    pub mercator: Mercator,
+    /// In Mercator
+    pub boundary: MultiPolygon,
+    /// Also keep stops this far outside the boundary, so people near the edge can still use them
+    pub buffer_meters: f64,
+    /// If specified, only keep trips running on some day in this inclusive range
+    pub dates: Option<(NaiveDate, NaiveDate)>,
+    /// In Mercator, including the buffer
+    bounds: Rect,
+}
+
+impl StudyArea {
+    pub fn new(
+        mercator: Mercator,
+        boundary: MultiPolygon,
+        buffer_meters: f64,
+        dates: Option<(NaiveDate, NaiveDate)>,
+    ) -> Self {
+        let bounds = match boundary.bounding_rect() {
+            Some(rect) => Rect::new(
+                Coord {
+                    x: rect.min().x - buffer_meters,
+                    y: rect.min().y - buffer_meters,
+                },
+                Coord {
+                    x: rect.max().x + buffer_meters,
+                    y: rect.max().y + buffer_meters,
+                },
+            ),
+            None => Rect::new(Coord::zero(), Coord::zero()),
+        };
+        Self {
+            mercator,
+            boundary,
+            buffer_meters,
+            dates,
+            bounds,
+        }
+    }
+
+    /// Is a WGS84 point inside the boundary or its buffer?
+    pub fn contains(&self, pt: Point) -> bool {
+        let pt = self.mercator.to_mercator(&pt);
+        // Most stops in a large feed are far away, so check the cheap thing first
+        if !self.bounds.contains(&pt) {
+            return false;
+        }
+        self.boundary.contains(&pt) || Euclidean.distance(&pt, &self.boundary) <= self.buffer_meters
+    }
+
+    /// A WGS84 rectangle covering everything this contains
+    pub fn wgs84_bounds(&self) -> Rect {
+        self.mercator.to_wgs84(&self.bounds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use geo::{LineString, Polygon, coord};
+
+    use super::*;
+
+    #[test]
+    fn concave_boundary() {
+        // An L shape, missing the top-right corner of its bounding box
+        let rect = Rect::new(coord! { x: -0.2, y: 51.4 }, coord! { x: 0.0, y: 51.6 });
+        let mercator = Mercator::from(rect).unwrap();
+        let l_shape = Polygon::new(
+            LineString::from(vec![
+                (-0.2, 51.4),
+                (0.0, 51.4),
+                (0.0, 51.5),
+                (-0.1, 51.5),
+                (-0.1, 51.6),
+                (-0.2, 51.6),
+                (-0.2, 51.4),
+            ]),
+            Vec::new(),
+        );
+        let boundary = mercator.to_mercator(&MultiPolygon(vec![l_shape]));
+
+        let inside = Point::new(-0.15, 51.55);
+        let in_corner = Point::new(-0.05, 51.55);
+        // About 35m from the edge of the L
+        let near_edge = Point::new(-0.0995, 51.55);
+
+        let area = StudyArea::new(mercator.clone(), boundary.clone(), 0.0, None);
+        assert!(area.contains(inside));
+        assert!(!area.contains(in_corner));
+        assert!(!area.contains(near_edge));
+
+        let area = StudyArea::new(mercator, boundary, 100.0, None);
+        assert!(area.contains(inside));
+        assert!(area.contains(near_edge));
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{Datelike, NaiveDate, Weekday};
+use serde::{Deserialize, Serialize};
+
+use super::ids::orig_ids;
+use super::{GtfsModel, ServiceID};
+
+/// Describes what days a `Trip` runs on. This combines the weekly pattern from `calendar.txt` with
+/// exceptions from `calendar_dates.txt`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Service {
+    pub orig_id: orig_ids::ServiceID,
+    /// Indexed by `Weekday::num_days_from_monday`
+    pub weekdays: [bool; 7],
+    /// The inclusive range of dates when `weekdays` applies. Services only defined through
+    /// `calendar_dates.txt` don't have this.
+    pub date_range: Option<(NaiveDate, NaiveDate)>,
+    /// Extra dates when the service runs, overriding the weekly pattern
+    pub added_dates: BTreeSet<NaiveDate>,
+    /// Dates when the service doesn't run, overriding the weekly pattern
+    pub removed_dates: BTreeSet<NaiveDate>,
+}
+
+impl Service {
+    pub fn new(orig_id: orig_ids::ServiceID) -> Self {
+        Self {
+            orig_id,
+            weekdays: [false; 7],
+            date_range: None,
+            added_dates: BTreeSet::new(),
+            removed_dates: BTreeSet::new(),
+        }
+    }
+
+    pub fn runs_on(&self, date: NaiveDate) -> bool {
+        if self.removed_dates.contains(&date) {
+            return false;
+        }
+        if self.added_dates.contains(&date) {
+            return true;
+        }
+        match self.date_range {
+            Some((start, end)) => {
+                start <= date
+                    && date <= end
+                    && self.weekdays[date.weekday().num_days_from_monday() as usize]
+            }
+            None => false,
+        }
+    }
+
+    /// Does this service run on any day in the inclusive range?
+    pub fn runs_between(&self, start: NaiveDate, end: NaiveDate) -> bool {
+        start
+            .iter_days()
+            .take_while(|date| *date <= end)
+            .any(|date| self.runs_on(date))
+    }
+
+    /// The earliest date mentioned by this service, if any
+    fn first_date(&self) -> Option<NaiveDate> {
+        let start = self.date_range.map(|(start, _)| start);
+        let added = self.added_dates.first().cloned();
+        match (start, added) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+impl GtfsModel {
+    pub fn runs_on(&self, service: ServiceID, date: NaiveDate) -> bool {
+        self.services[service.0].runs_on(date)
+    }
+
+    /// Queries don't always specify a date. Pick a Monday that most services run on, to be
+    /// representative of a normal weekday.
+    pub fn default_date(&self) -> NaiveDate {
+        // Consider the first Monday on or after the start of every service
+        let mut candidates = BTreeSet::new();
+        for service in &self.services {
+            if let Some(date) = service.first_date() {
+                let days_until_monday = (7 - date.weekday().num_days_from_monday()) % 7;
+                candidates.insert(date + chrono::Days::new(days_until_monday.into()));
+            }
+        }
+
+        let mut counts: BTreeMap<NaiveDate, usize> = BTreeMap::new();
+        for date in candidates {
+            counts.insert(
+                date,
+                self.services.iter().filter(|s| s.runs_on(date)).count(),
+            );
+        }
+        // Prefer the earliest date when there's a tie
+        counts
+            .into_iter()
+            .rev()
+            .max_by_key(|(_, count)| *count)
+            .map(|(date, _)| date)
+            .unwrap_or_else(|| {
+                // There's no transit at all, so it doesn't matter
+                NaiveDate::from_isoywd_opt(2024, 1, Weekday::Mon).unwrap()
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(x: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(x, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn exceptions_override_weekly_pattern() {
+        let mut service = Service::new(serde_json::from_str("\"weekdays\"").unwrap());
+        service.weekdays = [true, true, true, true, true, false, false];
+        service.date_range = Some((date("2024-01-01"), date("2024-12-31")));
+        // A Monday bank holiday and a special Sunday service
+        service.removed_dates.insert(date("2024-05-06"));
+        service.added_dates.insert(date("2024-05-12"));
+
+        assert!(service.runs_on(date("2024-05-07")));
+        assert!(!service.runs_on(date("2024-05-11")));
+        assert!(!service.runs_on(date("2024-05-06")));
+        assert!(service.runs_on(date("2024-05-12")));
+        // Outside the date range
+        assert!(!service.runs_on(date("2025-01-06")));
+    }
+}
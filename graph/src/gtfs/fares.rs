@@ -0,0 +1,146 @@
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::{Alighting, GtfsModel, NextStep, RouteID, StopID, TripID};
+
+/// The price of one leg, from boarding a trip to getting off it. Both Fares v1
+/// (`fare_attributes.txt` and `fare_rules.txt`) and Fares v2 (`fare_products.txt` and
+/// `fare_leg_rules.txt`) become these. Zones and areas are expanded into the stops they contain.
+///
+/// Discounts for transferring between legs aren't modelled yet; every leg pays its own fare.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FareRule {
+    pub price: f64,
+    /// An ISO 4217 code
+    pub currency: String,
+    /// If specified, only legs on these routes match
+    pub routes: Option<BTreeSet<RouteID>>,
+    /// If specified, only legs boarding at one of these stops match
+    pub from_stops: Option<BTreeSet<StopID>>,
+    /// If specified, only legs getting off at one of these stops match
+    pub to_stops: Option<BTreeSet<StopID>>,
+    /// From `rule_priority` in Fares v2. Higher values win.
+    pub priority: i32,
+}
+
+impl FareRule {
+    fn matches(&self, route: RouteID, board: StopID, alight: StopID) -> bool {
+        self.routes.as_ref().is_none_or(|x| x.contains(&route))
+            && self.from_stops.as_ref().is_none_or(|x| x.contains(&board))
+            && self.to_stops.as_ref().is_none_or(|x| x.contains(&alight))
+    }
+
+    fn specificity(&self) -> usize {
+        [
+            self.routes.is_some(),
+            self.from_stops.is_some(),
+            self.to_stops.is_some(),
+        ]
+        .into_iter()
+        .filter(|x| *x)
+        .count()
+    }
+}
+
+/// The fares paid so far by a search. Searches carry this around along with `Alighting`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FareSoFar {
+    /// The total for all finished legs
+    pub paid: f64,
+    /// Where the current leg boarded, if any
+    pub boarded: Option<StopID>,
+}
+
+// Searches need to store this in priority queues
+impl PartialEq for FareSoFar {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for FareSoFar {}
+impl PartialOrd for FareSoFar {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for FareSoFar {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.paid
+            .total_cmp(&other.paid)
+            .then_with(|| self.boarded.cmp(&other.boarded))
+    }
+}
+
+impl GtfsModel {
+    /// The fare for riding `trip` from `board` to `alight`. When several rules match, the one
+    /// with the highest priority wins, then the most specific, then the cheapest. Returns `None`
+    /// if the feed doesn't say.
+    pub fn leg_fare(&self, trip: TripID, board: StopID, alight: StopID) -> Option<&FareRule> {
+        let route = self.trips[trip.0].route;
+        self.fares
+            .iter()
+            .filter(|rule| rule.matches(route, board, alight))
+            .max_by(|a, b| {
+                a.priority
+                    .cmp(&b.priority)
+                    .then_with(|| a.specificity().cmp(&b.specificity()))
+                    .then_with(|| b.price.total_cmp(&a.price))
+            })
+    }
+
+    /// Somebody at `stop1` boards `next_step`, after previously getting off `prev` and paying
+    /// `fare`. Returns the new state and the total fare, including the leg in progress. Legs with
+    /// unknown fares are free.
+    pub fn ride_fare(
+        &self,
+        prev: Option<Alighting>,
+        mut fare: FareSoFar,
+        stop1: StopID,
+        next_step: &NextStep,
+    ) -> (FareSoFar, f64) {
+        if !prev.is_some_and(|prev| prev.stays_on(stop1, next_step)) {
+            if let (Some(prev), Some(board)) = (prev, fare.boarded) {
+                fare.paid += self.leg_price(prev.trip, board, prev.stop);
+            }
+            fare.boarded = Some(stop1);
+        }
+        let total =
+            fare.paid + self.leg_price(next_step.trip, fare.boarded.unwrap(), next_step.stop2);
+        (fare, total)
+    }
+
+    /// The total fare of everything ridden so far, after getting off `prev`
+    pub fn fare_paid(&self, prev: Option<Alighting>, fare: FareSoFar) -> f64 {
+        match (prev, fare.boarded) {
+            (Some(prev), Some(board)) => fare.paid + self.leg_price(prev.trip, board, prev.stop),
+            _ => fare.paid,
+        }
+    }
+
+    /// The one currency used by every fare, so that totals make sense. Fails if the feed has no
+    /// fares or mixes currencies.
+    pub fn fare_currency(&self) -> Result<&str> {
+        let currencies: BTreeSet<&str> = self
+            .fares
+            .iter()
+            .map(|rule| rule.currency.as_str())
+            .collect();
+        match currencies.len() {
+            0 => bail!("The GTFS data has no fares"),
+            1 => Ok(currencies.into_iter().next().unwrap()),
+            _ => bail!(
+                "The GTFS data has fares in different currencies: {}",
+                currencies.into_iter().collect::<Vec<_>>().join(", ")
+            ),
+        }
+    }
+
+    fn leg_price(&self, trip: TripID, board: StopID, alight: StopID) -> f64 {
+        self.leg_fare(trip, board, alight)
+            .map(|rule| rule.price)
+            .unwrap_or(0.0)
+    }
+}
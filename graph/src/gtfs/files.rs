@@ -0,0 +1,73 @@
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek};
+
+use anyhow::Result;
+use zip::ZipArchive;
+
+/// Zip files start with this
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+pub(crate) trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// The text files of a GTFS feed, either from an unzipped directory or a zip file
+pub(crate) enum GtfsFiles {
+    Dir(String),
+    Zip(ZipArchive<Box<dyn ReadSeek>>),
+}
+
+impl GtfsFiles {
+    /// Takes a path to a directory or a zip file, detecting which it is
+    pub fn open(path: &str) -> Result<Self> {
+        if std::fs::metadata(path)?.is_dir() {
+            return Ok(Self::Dir(path.to_string()));
+        }
+
+        let mut file = File::open(path)?;
+        let mut magic = [0; 4];
+        if file.read_exact(&mut magic).is_err() || magic != ZIP_MAGIC {
+            bail!("{path} isn't a GTFS directory or zip file");
+        }
+        file.rewind()?;
+        let reader: Box<dyn ReadSeek> = Box::new(BufReader::new(file));
+        Ok(Self::Zip(ZipArchive::new(reader)?))
+    }
+
+    /// Takes the bytes of a zip file
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self> {
+        if !bytes.starts_with(ZIP_MAGIC) {
+            bail!("GTFS input isn't a zip file");
+        }
+        let reader: Box<dyn ReadSeek> = Box::new(Cursor::new(bytes));
+        Ok(Self::Zip(ZipArchive::new(reader)?))
+    }
+
+    pub fn exists(&self, name: &str) -> Result<bool> {
+        match self {
+            Self::Dir(dir) => Ok(std::fs::exists(format!("{dir}/{name}"))?),
+            Self::Zip(archive) => Ok(zip_entry(archive, name).is_some()),
+        }
+    }
+
+    pub fn csv(&mut self, name: &str) -> Result<csv::Reader<Box<dyn Read + '_>>> {
+        let reader: Box<dyn Read + '_> = match self {
+            Self::Dir(dir) => Box::new(File::open(format!("{dir}/{name}"))?),
+            Self::Zip(archive) => {
+                let Some(entry) = zip_entry(archive, name) else {
+                    bail!("{name} is missing from the zip file");
+                };
+                Box::new(archive.by_name(&entry)?)
+            }
+        };
+        Ok(csv::Reader::from_reader(reader))
+    }
+}
+
+/// Finds the full name of a file in the zip. Some feeds put everything inside a directory.
+fn zip_entry(archive: &ZipArchive<Box<dyn ReadSeek>>, name: &str) -> Option<String> {
+    let suffix = format!("/{name}");
+    archive
+        .file_names()
+        .find(|x| *x == name || x.ends_with(&suffix))
+        .map(|x| x.to_string())
+}
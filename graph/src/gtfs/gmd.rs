@@ -1,14 +1,21 @@
+use std::io::{Read, Seek};
+use std::ops::Range;
+use std::time::Duration;
+
 use anyhow::{Result, bail};
-use chrono::NaiveTime;
+use chrono::NaiveDate;
 use futures_util::StreamExt;
-use geo::{Contains, Coord, LineString};
+use geo::{Coord, LineString, Point};
 use geomedea::{Bounds, Geometry, LngLat, Properties, PropertyValue};
-use utils::Mercator;
 
-use super::{GtfsModel, Route, RouteID, Stop, StopID, Trip, orig_ids};
-use crate::RoadID;
+use super::{
+    Agency, Entrance, GtfsModel, Route, RouteID, Service, ServiceID, ServiceTime, Shape, ShapeID,
+    Station, StationID, Stop, StopID, StopTime, StudyArea, Transfer, Trip, orig_ids,
+};
 
 impl GtfsModel {
+    /// Fares apply across the whole feed, so they don't fit into per-variant features and aren't
+    /// written. Models loaded from geomedea have no fares, so `max_fare` can't be used with them.
     #[cfg(not(target_arch = "wasm32"))]
     pub fn to_geomedea(&self, filename: &str) -> Result<()> {
         use std::fs::File;
@@ -16,6 +23,13 @@ impl GtfsModel {
 
         use geomedea::{Feature, Geometry, Writer};
 
+        if !self.fares.is_empty() {
+            warn!(
+                "Not writing {} fare rules to {filename}; geomedea files don't support fares",
+                self.fares.len()
+            );
+        }
+
         let out = BufWriter::new(File::create(filename)?);
         let mut writer = Writer::new(out, true)?;
 
@@ -37,106 +51,278 @@ impl GtfsModel {
         Ok(())
     }
 
-    pub async fn from_geomedea(url: &str, mercator: &Mercator) -> Result<Self> {
-        let bbox = &mercator.wgs84_bounds;
+    /// Loads a geomedea file from a URL, only downloading the parts covering the study area
+    pub async fn from_geomedea(url: &str, area: &StudyArea) -> Result<Self> {
         let mut reader = geomedea::HttpReader::open(url).await?;
-        let mut feature_stream = reader
-            .select_bbox(&Bounds::from_corners(
-                &LngLat::degrees(bbox.min().x, bbox.min().y),
-                &LngLat::degrees(bbox.max().x, bbox.max().y),
-            ))
-            .await?;
-
-        let mut gtfs = GtfsModel::empty();
+        let mut feature_stream = reader.select_bbox(&geomedea_bounds(area)).await?;
+        let mut loader = Loader::new();
         while let Some(feature) = feature_stream.next().await {
-            let feature = feature?;
-            let (geometry, properties) = feature.into_inner();
-
-            let variant = RouteVariant::decode(properties)?;
-
-            let linestring = match geometry {
-                Geometry::LineString(ls) => LineString::new(
-                    ls.points()
-                        .into_iter()
-                        .map(|pt| Coord {
-                            x: pt.lng_degrees(),
-                            y: pt.lat_degrees(),
-                        })
-                        .collect(),
-                ),
-                _ => bail!("Wrong Geometry type"),
-            };
+            loader.add_feature(feature?, area)?;
+        }
+        Ok(loader.finish())
+    }
 
-            // Fill out the stops
-            let mut stop_ids = Vec::new();
-            // Have a true/false for each entry in the full stop_sequence
-            let mut keep_stops = Vec::new();
-            for ((orig_stop_id, stop_name), point) in
-                variant.stop_info.into_iter().zip(linestring.points())
-            {
-                // Mimic what scrape.rs does, removing stops outside the bounding box.
-                // TODO Be even more precise -- inside the polygon
-                if !mercator.wgs84_bounds.contains(&point) {
-                    keep_stops.push(false);
-                    continue;
-                }
-                keep_stops.push(true);
-
-                stop_ids.push(
-                    if let Some(idx) = gtfs.stops.iter().position(|s| s.orig_id == orig_stop_id) {
-                        StopID(idx)
-                    } else {
-                        gtfs.stops.push(Stop {
-                            name: stop_name,
-                            orig_id: orig_stop_id,
-                            point: mercator.to_mercator(&point),
-                            // Will fill out later
-                            road: RoadID(0),
-                            next_steps: Vec::new(),
-                        });
-                        StopID(gtfs.stops.len() - 1)
-                    },
-                );
+    /// Loads a local geomedea file
+    pub fn from_geomedea_file(path: &str, area: &StudyArea) -> Result<Self> {
+        let file = std::io::BufReader::new(std::fs::File::open(path)?);
+        Self::from_geomedea_reader(file, area)
+    }
+
+    /// Loads a geomedea file already in memory
+    pub fn from_geomedea_bytes(bytes: &[u8], area: &StudyArea) -> Result<Self> {
+        Self::from_geomedea_reader(std::io::Cursor::new(bytes), area)
+    }
+
+    fn from_geomedea_reader<R: Read + Seek>(reader: R, area: &StudyArea) -> Result<Self> {
+        let mut reader = geomedea::Reader::new(reader)?;
+        let mut loader = Loader::new();
+        for feature in reader.select_bbox(&geomedea_bounds(area))? {
+            loader.add_feature(feature?, area)?;
+        }
+        Ok(loader.finish())
+    }
+}
+
+fn geomedea_bounds(area: &StudyArea) -> Bounds {
+    let bbox = area.wgs84_bounds();
+    Bounds::from_corners(
+        &LngLat::degrees(bbox.min().x, bbox.min().y),
+        &LngLat::degrees(bbox.max().x, bbox.max().y),
+    )
+}
+
+/// Builds a `GtfsModel` from route variants, however they're read
+struct Loader {
+    gtfs: GtfsModel,
+    // Stops might be defined in a later variant, so resolve these at the end
+    all_transfers: Vec<(orig_ids::StopID, orig_ids::StopID, Transfer)>,
+    // Platforms too. Entrance points are already projected.
+    all_entrances: Vec<(StationID, VariantEntrance)>,
+}
+
+impl Loader {
+    fn new() -> Self {
+        Self {
+            gtfs: GtfsModel::empty(),
+            all_transfers: Vec::new(),
+            all_entrances: Vec::new(),
+        }
+    }
+
+    fn add_feature(&mut self, feature: geomedea::Feature, area: &StudyArea) -> Result<()> {
+        let gtfs = &mut self.gtfs;
+        let (geometry, properties) = feature.into_inner();
+
+        let variant = RouteVariant::decode(properties)?;
+        self.all_transfers.extend(variant.transfers);
+
+        let linestring = match geometry {
+            Geometry::LineString(ls) => LineString::new(
+                ls.points()
+                    .into_iter()
+                    .map(|pt| Coord {
+                        x: pt.lng_degrees(),
+                        y: pt.lat_degrees(),
+                    })
+                    .collect(),
+            ),
+            _ => bail!("Wrong Geometry type"),
+        };
+
+        // Fill out the stops. Mimic what scrape.rs does, removing stops outside the study area.
+        // Have an entry for each stop in the full stop_sequence.
+        let mut stop_ids: Vec<Option<StopID>> = Vec::new();
+        for ((((orig_stop_id, stop_name), point), wheelchair), station) in variant
+            .stop_info
+            .into_iter()
+            .zip(linestring.points())
+            .zip(variant.stop_wheelchair)
+            .zip(variant.stop_stations)
+        {
+            if !area.contains(point) {
+                stop_ids.push(None);
+                continue;
             }
 
-            // If all stops were out of bounds, we got something totally irrelevant
-            if stop_ids.is_empty() {
+            if let Some(idx) = gtfs.stops.iter().position(|s| s.orig_id == orig_stop_id) {
+                stop_ids.push(Some(StopID(idx)));
                 continue;
             }
+            let stop_id = StopID(gtfs.stops.len());
+            // Stations are only created once one of their platforms is kept
+            let station = station.map(|idx| {
+                let station = &variant.stations[idx];
+                let station_id = match gtfs
+                    .stations
+                    .iter()
+                    .position(|s| s.orig_id == station.orig_id)
+                {
+                    Some(idx) => StationID(idx),
+                    None => {
+                        // Every variant through the station has the same entrances
+                        let station_id = StationID(gtfs.stations.len());
+                        gtfs.stations.push(Station {
+                            name: station.name.clone(),
+                            orig_id: station.orig_id.clone(),
+                            point: area.mercator.to_mercator(&station.point),
+                            platforms: Vec::new(),
+                            entrances: Vec::new(),
+                        });
+                        self.all_entrances.extend(
+                            station
+                                .entrances
+                                .iter()
+                                .filter(|e| area.contains(e.point))
+                                .map(|e| {
+                                    let entrance = VariantEntrance {
+                                        point: area.mercator.to_mercator(&e.point),
+                                        ..e.clone()
+                                    };
+                                    (station_id, entrance)
+                                }),
+                        );
+                        station_id
+                    }
+                };
+                gtfs.stations[station_id.0].platforms.push(stop_id);
+                station_id
+            });
+            gtfs.stops.push(Stop {
+                name: stop_name,
+                orig_id: orig_stop_id,
+                point: area.mercator.to_mercator(&point),
+                station,
+                wheelchair_boarding: wheelchair,
+                // Will fill out later
+                access: Vec::new(),
+                departures: Vec::new(),
+            });
+            stop_ids.push(Some(stop_id));
+        }
+
+        // If the variant leaves the study area and comes back, split every trip into pieces
+        let pieces = kept_ranges(&stop_ids);
+        // If all stops were out of bounds, we got something totally irrelevant
+        if pieces.is_empty() {
+            return Ok(());
+        }
 
-            // Fill out the route
-            let route_id = if let Some(idx) = gtfs
-                .routes
-                .iter()
-                .position(|r| r.orig_id == variant.route.orig_id)
+        // Fill out the route
+        let route_id = if let Some(idx) = gtfs
+            .routes
+            .iter()
+            .position(|r| r.orig_id == variant.route.orig_id)
+        {
+            RouteID(idx)
+        } else {
+            gtfs.routes.push(variant.route);
+            RouteID(gtfs.routes.len() - 1)
+        };
+
+        // Fill out services, skipping any not running when needed
+        let mut service_ids = Vec::new();
+        for service in variant.services {
+            if let Some((start, end)) = area.dates
+                && !service.runs_between(start, end)
             {
-                RouteID(idx)
-            } else {
-                gtfs.routes.push(variant.route);
-                RouteID(gtfs.routes.len() - 1)
-            };
+                service_ids.push(None);
+                continue;
+            }
+            service_ids.push(Some(
+                if let Some(idx) = gtfs
+                    .services
+                    .iter()
+                    .position(|s| s.orig_id == service.orig_id)
+                {
+                    ServiceID(idx)
+                } else {
+                    gtfs.services.push(service);
+                    ServiceID(gtfs.services.len() - 1)
+                },
+            ));
+        }
 
-            // Fill out trips
-            for times in variant.trips {
-                // We might've clipped out some stops
-                let clipped_times = times
-                    .into_iter()
-                    .zip(&keep_stops)
-                    .filter(|(_, ok)| **ok)
-                    .map(|(t, _)| t)
-                    .collect::<Vec<_>>();
-                assert_eq!(stop_ids.len(), clipped_times.len());
+        // Fill out shapes, once per piece
+        let mut shape_ids: Vec<Vec<ShapeID>> = Vec::new();
+        for shape in variant.shapes {
+            let linestring = area.mercator.to_mercator(&shape.linestring);
+            let mut per_piece = Vec::new();
+            for piece in &pieces {
+                gtfs.shapes.push(Shape {
+                    linestring: linestring.clone(),
+                    stop_fractions: shape.stop_fractions[piece.clone()].to_vec(),
+                });
+                per_piece.push(ShapeID(gtfs.shapes.len() - 1));
+            }
+            shape_ids.push(per_piece);
+        }
 
+        // Fill out trips
+        for ((((orig_id, times), service), shape), wheelchair) in variant
+            .trip_ids
+            .into_iter()
+            .zip(variant.trips)
+            .zip(variant.trip_services)
+            .zip(variant.trip_shapes)
+            .zip(variant.trip_wheelchair)
+        {
+            let Some(service) = service_ids[service] else {
+                continue;
+            };
+            for (piece_idx, piece) in pieces.iter().enumerate() {
                 gtfs.trips.push(Trip {
-                    stop_sequence: stop_ids.clone().into_iter().zip(clipped_times).collect(),
+                    orig_id: orig_id.clone(),
+                    start_time: times[0].0,
+                    stop_sequence: piece
+                        .clone()
+                        .map(|idx| StopTime {
+                            stop: stop_ids[idx].unwrap(),
+                            arrival: times[idx].0,
+                            departure: times[idx].1,
+                        })
+                        .collect(),
                     route: route_id,
+                    service,
+                    shape: shape.map(|idx| shape_ids[idx][piece_idx]),
+                    wheelchair_accessible: wheelchair,
                 });
             }
         }
+        Ok(())
+    }
 
-        gtfs.precompute_next_steps();
+    fn finish(self) -> GtfsModel {
+        let mut gtfs = self.gtfs;
+        for (from, to, transfer) in self.all_transfers {
+            // Out-of-bounds stops won't be found
+            let (Some(from), Some(to)) = (
+                gtfs.stops.iter().position(|s| s.orig_id == from),
+                gtfs.stops.iter().position(|s| s.orig_id == to),
+            ) else {
+                continue;
+            };
+            gtfs.transfers.insert((StopID(from), StopID(to)), transfer);
+        }
+        for (station_id, entrance) in self.all_entrances {
+            let mut platforms = Vec::new();
+            for (platform, enter, exit) in entrance.platforms {
+                // Out-of-bounds platforms won't be found
+                if let Some(idx) = gtfs.stops.iter().position(|s| s.orig_id == platform) {
+                    platforms.push((StopID(idx), enter, exit));
+                }
+            }
+            gtfs.stations[station_id.0].entrances.push(Entrance {
+                name: entrance.name,
+                orig_id: entrance.orig_id,
+                point: entrance.point,
+                wheelchair_boarding: entrance.wheelchair_boarding,
+                platforms,
+            });
+        }
+
+        gtfs.build_timetable();
 
-        Ok(gtfs)
+        gtfs
     }
 }
 
@@ -144,43 +330,385 @@ struct RouteVariant {
     // Per stop, (original ID and name)
     pub stop_info: Vec<(orig_ids::StopID, String)>,
 
-    // Each one has an arrival time per stop
-    pub trips: Vec<Vec<NaiveTime>>,
+    // Each one has an (arrival, departure) time per stop. Frequency-based trips are already
+    // expanded.
+    pub trips: Vec<Vec<(ServiceTime, ServiceTime)>>,
+    // Per trip, the original ID
+    pub trip_ids: Vec<orig_ids::TripID>,
+    // Per trip, an index into services
+    pub trip_services: Vec<usize>,
+    // Per trip, wheelchair_accessible
+    pub trip_wheelchair: Vec<Option<bool>>,
+    // Per stop, wheelchair_boarding
+    pub stop_wheelchair: Vec<Option<bool>>,
+    // Per stop, an optional index into stations
+    pub stop_stations: Vec<Option<usize>>,
+    // All of the stations containing stops in this variant, in WGS84
+    pub stations: Vec<VariantStation>,
 
     // Metadata
     pub route: Route,
+    // All of the services used by trips in this variant
+    pub services: Vec<Service>,
+    // Per trip, an optional index into shapes
+    pub trip_shapes: Vec<Option<usize>>,
+    // All of the shapes used by trips in this variant, in WGS84
+    pub shapes: Vec<Shape>,
+    // Rules about changing trips, starting from any stop in this variant
+    pub transfers: Vec<(orig_ids::StopID, orig_ids::StopID, Transfer)>,
 }
 
+/// A station with everything needed to reach its platforms from the street, using original IDs
+#[derive(Clone)]
+struct VariantStation {
+    orig_id: orig_ids::StopID,
+    name: String,
+    point: Point,
+    entrances: Vec<VariantEntrance>,
+}
+
+#[derive(Clone)]
+struct VariantEntrance {
+    orig_id: orig_ids::StopID,
+    name: String,
+    point: Point,
+    wheelchair_boarding: Option<bool>,
+    // Per platform, the time to walk there and back, already worked out from pathways
+    platforms: Vec<(orig_ids::StopID, Duration, Duration)>,
+}
+
+/// Bump this whenever the properties written by `RouteVariant::encode` change
+const FORMAT_VERSION: u32 = 3;
+
 impl RouteVariant {
+    /// Version 3 of the format. Stop info, stations, the route and its agency, services, and
+    /// transfers are stored as separate lists of plain values, not JSON. Trip times are
+    /// delta-encoded, since consecutive stops are close in time.
     #[cfg(not(target_arch = "wasm32"))]
     fn encode(&self) -> Result<Properties> {
-        use chrono::Timelike;
-
         let mut props = Properties::empty();
+        props.insert("version".to_string(), PropertyValue::UInt32(FORMAT_VERSION));
+
+        props.insert(
+            "stop_ids".to_string(),
+            encode_strings(self.stop_info.iter().map(|(id, _)| id.as_str())),
+        );
+        props.insert(
+            "stop_names".to_string(),
+            encode_strings(self.stop_info.iter().map(|(_, name)| name.as_str())),
+        );
+        props.insert(
+            "stop_wheelchair".to_string(),
+            encode_wheelchair(&self.stop_wheelchair),
+        );
+        // 0 means no station, otherwise it's 1 + the index into stations
+        props.insert(
+            "stop_stations".to_string(),
+            encode_optional_indices(&self.stop_stations),
+        );
+
+        props.insert(
+            "station_ids".to_string(),
+            encode_strings(self.stations.iter().map(|s| s.orig_id.as_str())),
+        );
+        props.insert(
+            "station_names".to_string(),
+            encode_strings(self.stations.iter().map(|s| s.name.as_str())),
+        );
+        props.insert(
+            "station_points".to_string(),
+            encode_points(self.stations.iter().map(|s| s.point)),
+        );
+        // Entrances from every station are flattened into one list
+        let entrances: Vec<(usize, &VariantEntrance)> = self
+            .stations
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, s)| s.entrances.iter().map(move |e| (idx, e)))
+            .collect();
+        props.insert(
+            "entrance_stations".to_string(),
+            PropertyValue::Vec(
+                entrances
+                    .iter()
+                    .map(|(idx, _)| PropertyValue::UInt32(*idx as u32))
+                    .collect(),
+            ),
+        );
+        props.insert(
+            "entrance_ids".to_string(),
+            encode_strings(entrances.iter().map(|(_, e)| e.orig_id.as_str())),
+        );
+        props.insert(
+            "entrance_names".to_string(),
+            encode_strings(entrances.iter().map(|(_, e)| e.name.as_str())),
+        );
+        props.insert(
+            "entrance_points".to_string(),
+            encode_points(entrances.iter().map(|(_, e)| e.point)),
+        );
+        props.insert(
+            "entrance_wheelchair".to_string(),
+            encode_wheelchair(
+                &entrances
+                    .iter()
+                    .map(|(_, e)| e.wheelchair_boarding)
+                    .collect::<Vec<_>>(),
+            ),
+        );
+        props.insert(
+            "entrance_platforms".to_string(),
+            PropertyValue::Vec(
+                entrances
+                    .iter()
+                    .map(|(_, e)| encode_strings(e.platforms.iter().map(|(id, _, _)| id.as_str())))
+                    .collect(),
+            ),
+        );
+        // The time in seconds to walk to each platform, then back
+        props.insert(
+            "entrance_platform_seconds".to_string(),
+            PropertyValue::Vec(
+                entrances
+                    .iter()
+                    .map(|(_, e)| {
+                        PropertyValue::Vec(
+                            e.platforms
+                                .iter()
+                                .flat_map(|(_, enter, exit)| {
+                                    [enter, exit].map(|x| PropertyValue::UInt32(x.as_secs() as u32))
+                                })
+                                .collect(),
+                        )
+                    })
+                    .collect(),
+            ),
+        );
+
+        props.insert(
+            "route_id".to_string(),
+            PropertyValue::String(self.route.orig_id.as_str().to_string()),
+        );
+        // Leave out missing names
+        for (key, value) in [
+            ("route_short_name", &self.route.short_name),
+            ("route_long_name", &self.route.long_name),
+            ("route_description", &self.route.description),
+        ] {
+            if let Some(value) = value {
+                props.insert(key.to_string(), PropertyValue::String(value.clone()));
+            }
+        }
+        props.insert(
+            "route_type".to_string(),
+            PropertyValue::UInt32(self.route.route_type.into()),
+        );
+        if let Some(ref agency) = self.route.agency {
+            for (key, value) in [
+                ("agency_id", agency.orig_id.as_str()),
+                ("agency_name", &agency.name),
+                ("agency_url", &agency.url),
+                ("agency_timezone", &agency.timezone),
+            ] {
+                props.insert(key.to_string(), PropertyValue::String(value.to_string()));
+            }
+        }
+
+        props.insert(
+            "service_ids".to_string(),
+            encode_strings(self.services.iter().map(|s| s.orig_id.as_str())),
+        );
+        // Bit 0 is Monday
+        props.insert(
+            "service_weekdays".to_string(),
+            PropertyValue::Vec(
+                self.services
+                    .iter()
+                    .map(|s| {
+                        PropertyValue::UInt8(
+                            s.weekdays
+                                .iter()
+                                .enumerate()
+                                .map(|(idx, runs)| u8::from(*runs) << idx)
+                                .sum(),
+                        )
+                    })
+                    .collect(),
+            ),
+        );
+        // Either empty or the first and last date
+        props.insert(
+            "service_date_ranges".to_string(),
+            PropertyValue::Vec(
+                self.services
+                    .iter()
+                    .map(|s| encode_dates(s.date_range.iter().flat_map(|(a, b)| [a, b])))
+                    .collect(),
+            ),
+        );
+        props.insert(
+            "service_added_dates".to_string(),
+            PropertyValue::Vec(
+                self.services
+                    .iter()
+                    .map(|s| encode_dates(s.added_dates.iter()))
+                    .collect(),
+            ),
+        );
+        props.insert(
+            "service_removed_dates".to_string(),
+            PropertyValue::Vec(
+                self.services
+                    .iter()
+                    .map(|s| encode_dates(s.removed_dates.iter()))
+                    .collect(),
+            ),
+        );
+
+        props.insert(
+            "transfer_from".to_string(),
+            encode_strings(self.transfers.iter().map(|(from, _, _)| from.as_str())),
+        );
+        props.insert(
+            "transfer_to".to_string(),
+            encode_strings(self.transfers.iter().map(|(_, to, _)| to.as_str())),
+        );
+        // Uses the GTFS transfer_type, and the minimum time when that's 2
+        props.insert(
+            "transfer_types".to_string(),
+            PropertyValue::Vec(
+                self.transfers
+                    .iter()
+                    .map(|(_, _, transfer)| {
+                        PropertyValue::UInt8(match transfer {
+                            Transfer::Timed => 1,
+                            Transfer::MinTime(_) => 2,
+                            Transfer::Forbidden => 3,
+                        })
+                    })
+                    .collect(),
+            ),
+        );
+        props.insert(
+            "transfer_min_seconds".to_string(),
+            PropertyValue::Vec(
+                self.transfers
+                    .iter()
+                    .map(|(_, _, transfer)| {
+                        PropertyValue::UInt32(match transfer {
+                            Transfer::MinTime(min) => min.as_secs() as u32,
+                            Transfer::Timed | Transfer::Forbidden => 0,
+                        })
+                    })
+                    .collect(),
+            ),
+        );
+
+        props.insert(
+            "trip_ids".to_string(),
+            encode_strings(self.trip_ids.iter().map(|id| id.as_str())),
+        );
+        props.insert(
+            "trip_services".to_string(),
+            PropertyValue::Vec(
+                self.trip_services
+                    .iter()
+                    .map(|idx| PropertyValue::UInt32(*idx as u32))
+                    .collect(),
+            ),
+        );
+        props.insert(
+            "trip_wheelchair".to_string(),
+            encode_wheelchair(&self.trip_wheelchair),
+        );
 
-        // These two fields aren't usually too big, so just use JSON
+        // Shapes can be large, so avoid JSON
+        props.insert(
+            "shapes".to_string(),
+            PropertyValue::Vec(
+                self.shapes
+                    .iter()
+                    .map(|shape| {
+                        PropertyValue::Vec(
+                            shape
+                                .linestring
+                                .coords()
+                                .flat_map(|c| {
+                                    [PropertyValue::Float64(c.x), PropertyValue::Float64(c.y)]
+                                })
+                                .collect(),
+                        )
+                    })
+                    .collect(),
+            ),
+        );
         props.insert(
-            "stop_info".to_string(),
-            PropertyValue::Bytes(serde_json::to_vec(&self.stop_info)?),
+            "shape_stop_fractions".to_string(),
+            PropertyValue::Vec(
+                self.shapes
+                    .iter()
+                    .map(|shape| {
+                        PropertyValue::Vec(
+                            shape
+                                .stop_fractions
+                                .iter()
+                                .map(|x| PropertyValue::Float64(*x))
+                                .collect(),
+                        )
+                    })
+                    .collect(),
+            ),
         );
+        // 0 means no shape, otherwise it's 1 + the index into shapes
         props.insert(
-            "route".to_string(),
-            PropertyValue::Bytes(serde_json::to_vec(&self.route)?),
+            "trip_shapes".to_string(),
+            encode_optional_indices(&self.trip_shapes),
         );
 
-        // NaiveTime's serde encodes as strings by default! For GTFS arrival times, we don't even
-        // care about subsecond precision.
-        // TODO Some kind of delta encoding here could probably be useful
+        // For GTFS arrival times, we don't care about subsecond precision. Times may go past 24
+        // hours. Per trip, the first value is the arrival at the first stop, and then each value
+        // is the time from leaving the previous stop to arriving at the next. Bad input could make
+        // these negative.
         props.insert(
             "trips".to_string(),
             PropertyValue::Vec(
                 self.trips
                     .iter()
                     .map(|times| {
+                        let mut prev_departure = 0;
                         PropertyValue::Vec(
                             times
                                 .iter()
-                                .map(|t| PropertyValue::UInt32(t.num_seconds_from_midnight()))
+                                .map(|(arrival, departure)| {
+                                    let delta = arrival.seconds() as i32 - prev_departure;
+                                    prev_departure = departure.seconds() as i32;
+                                    PropertyValue::Int32(delta)
+                                })
+                                .collect(),
+                        )
+                    })
+                    .collect(),
+            ),
+        );
+        // Most trips don't wait at any stops, so use an empty list for those
+        props.insert(
+            "dwells".to_string(),
+            PropertyValue::Vec(
+                self.trips
+                    .iter()
+                    .map(|times| {
+                        if times
+                            .iter()
+                            .all(|(arrival, departure)| arrival == departure)
+                        {
+                            return PropertyValue::Vec(Vec::new());
+                        }
+                        PropertyValue::Vec(
+                            times
+                                .iter()
+                                .map(|(arrival, departure)| {
+                                    PropertyValue::UInt32((*departure - *arrival).as_secs() as u32)
+                                })
                                 .collect(),
                         )
                     })
@@ -192,37 +720,296 @@ impl RouteVariant {
     }
 
     fn decode(props: Properties) -> Result<Self> {
-        let stop_info = match props.get("stop_info") {
-            Some(PropertyValue::Bytes(bytes)) => serde_json::from_slice(bytes)?,
-            _ => bail!("stop_info missing or wrong type"),
-        };
-        let route = match props.get("route") {
-            Some(PropertyValue::Bytes(bytes)) => serde_json::from_slice(bytes)?,
-            _ => bail!("route missing or wrong type"),
+        match props.get("version") {
+            Some(PropertyValue::UInt32(version)) if *version == FORMAT_VERSION => {}
+            Some(PropertyValue::UInt32(version)) => bail!(
+                "this file uses version {version} of the transit format, but this code only reads version {FORMAT_VERSION}, so rebuild it with the same version of the code"
+            ),
+            Some(_) => bail!("version has wrong type"),
+            // The first version wasn't labelled
+            None => bail!(
+                "this file uses version 1 of the transit format, but version {FORMAT_VERSION} is needed, so rebuild it"
+            ),
+        }
+
+        let stop_ids = decode_strings(&props, "stop_ids")?;
+        let stop_names = decode_strings(&props, "stop_names")?;
+        if stop_ids.len() != stop_names.len() {
+            bail!("stop_ids and stop_names have different lengths");
+        }
+        let stop_info: Vec<(orig_ids::StopID, String)> = stop_ids
+            .iter()
+            .map(|id| orig_ids::StopID::new(id))
+            .zip(stop_names)
+            .collect();
+        let stop_wheelchair = decode_wheelchair(&props, "stop_wheelchair", stop_info.len())?;
+
+        let station_ids = decode_strings(&props, "station_ids")?;
+        let station_names = decode_strings(&props, "station_names")?;
+        let Some(station_points) = props.get("station_points") else {
+            bail!("station_points missing");
         };
+        let station_points = decode_points(station_points, "station_points")?;
+        if station_names.len() != station_ids.len() || station_points.len() != station_ids.len() {
+            bail!("station_ids, station_names, and station_points have different lengths");
+        }
+        let mut stations: Vec<VariantStation> = station_ids
+            .iter()
+            .zip(station_names)
+            .zip(station_points)
+            .map(|((id, name), point)| VariantStation {
+                orig_id: orig_ids::StopID::new(id),
+                name,
+                point,
+                entrances: Vec::new(),
+            })
+            .collect();
+        let stop_stations =
+            decode_optional_indices(&props, "stop_stations", stop_info.len(), stations.len())?;
 
-        let mut trips = Vec::new();
-        let Some(PropertyValue::Vec(raw_trips)) = props.get("trips") else {
-            bail!("trips missing or wrong type");
+        let entrance_ids = decode_strings(&props, "entrance_ids")?;
+        let num_entrances = entrance_ids.len();
+        let entrance_names = decode_strings(&props, "entrance_names")?;
+        let Some(entrance_points) = props.get("entrance_points") else {
+            bail!("entrance_points missing");
         };
-        for trip in raw_trips {
+        let entrance_points = decode_points(entrance_points, "entrance_points")?;
+        if entrance_names.len() != num_entrances || entrance_points.len() != num_entrances {
+            bail!("entrance_ids, entrance_names, and entrance_points have different lengths");
+        }
+        let entrance_stations = decode_list(&props, "entrance_stations", num_entrances)?;
+        let entrance_wheelchair = decode_wheelchair(&props, "entrance_wheelchair", num_entrances)?;
+        let entrance_platforms = decode_list(&props, "entrance_platforms", num_entrances)?;
+        let entrance_platform_seconds =
+            decode_list(&props, "entrance_platform_seconds", num_entrances)?;
+        for (idx, ((id, name), point)) in entrance_ids
+            .iter()
+            .zip(entrance_names)
+            .zip(entrance_points)
+            .enumerate()
+        {
+            let PropertyValue::UInt32(station) = entrance_stations[idx] else {
+                bail!("wrong inner type inside entrance_stations");
+            };
+            let Some(station) = stations.get_mut(station as usize) else {
+                bail!("entrance_stations refers to an unknown station");
+            };
+            let (PropertyValue::Vec(platforms), PropertyValue::Vec(seconds)) =
+                (&entrance_platforms[idx], &entrance_platform_seconds[idx])
+            else {
+                bail!("wrong inner type inside entrance_platforms or entrance_platform_seconds");
+            };
+            if seconds.len() != 2 * platforms.len() {
+                bail!("entrance_platform_seconds has the wrong length");
+            }
             let mut times = Vec::new();
-            let PropertyValue::Vec(raw_times) = trip else {
-                bail!("wrong inner type inside trips");
+            for (platform, pair) in platforms.iter().zip(seconds.chunks(2)) {
+                let (
+                    PropertyValue::String(platform),
+                    [PropertyValue::UInt32(enter), PropertyValue::UInt32(exit)],
+                ) = (platform, pair)
+                else {
+                    bail!(
+                        "wrong inner type inside entrance_platforms or entrance_platform_seconds"
+                    );
+                };
+                times.push((
+                    orig_ids::StopID::new(platform),
+                    Duration::from_secs((*enter).into()),
+                    Duration::from_secs((*exit).into()),
+                ));
+            }
+            station.entrances.push(VariantEntrance {
+                orig_id: orig_ids::StopID::new(id),
+                name,
+                point,
+                wheelchair_boarding: entrance_wheelchair[idx],
+                platforms: times,
+            });
+        }
+
+        let Some(PropertyValue::String(route_id)) = props.get("route_id") else {
+            bail!("route_id missing or wrong type");
+        };
+        let optional_string = |key: &str| match props.get(key) {
+            Some(PropertyValue::String(x)) => Ok(Some(x.clone())),
+            Some(_) => bail!("{key} has wrong type"),
+            None => Ok(None),
+        };
+        let Some(PropertyValue::UInt32(route_type)) = props.get("route_type") else {
+            bail!("route_type missing or wrong type");
+        };
+        let route = Route {
+            orig_id: orig_ids::RouteID::new(route_id),
+            short_name: optional_string("route_short_name")?,
+            long_name: optional_string("route_long_name")?,
+            description: optional_string("route_description")?,
+            route_type: (*route_type).try_into()?,
+            agency: match optional_string("agency_id")? {
+                Some(id) => Some(Agency {
+                    orig_id: orig_ids::AgencyID::new(&id),
+                    name: optional_string("agency_name")?.unwrap_or_default(),
+                    url: optional_string("agency_url")?.unwrap_or_default(),
+                    timezone: optional_string("agency_timezone")?.unwrap_or_default(),
+                }),
+                None => None,
+            },
+        };
+
+        let service_ids = decode_strings(&props, "service_ids")?;
+        let service_weekdays = decode_list(&props, "service_weekdays", service_ids.len())?;
+        let service_date_ranges = decode_list(&props, "service_date_ranges", service_ids.len())?;
+        let service_added_dates = decode_list(&props, "service_added_dates", service_ids.len())?;
+        let service_removed_dates =
+            decode_list(&props, "service_removed_dates", service_ids.len())?;
+        let mut services = Vec::new();
+        for (idx, id) in service_ids.iter().enumerate() {
+            let PropertyValue::UInt8(weekdays) = service_weekdays[idx] else {
+                bail!("wrong inner type inside service_weekdays");
+            };
+            let mut service = Service::new(orig_ids::ServiceID::new(id));
+            for (day, runs) in service.weekdays.iter_mut().enumerate() {
+                *runs = weekdays & (1 << day) != 0;
+            }
+            service.date_range =
+                match decode_dates(&service_date_ranges[idx], "service_date_ranges")?[..] {
+                    [] => None,
+                    [start, end] => Some((start, end)),
+                    _ => bail!("service_date_ranges has the wrong length"),
+                };
+            service.added_dates = decode_dates(&service_added_dates[idx], "service_added_dates")?
+                .into_iter()
+                .collect();
+            service.removed_dates =
+                decode_dates(&service_removed_dates[idx], "service_removed_dates")?
+                    .into_iter()
+                    .collect();
+            services.push(service);
+        }
+
+        let transfer_from = decode_strings(&props, "transfer_from")?;
+        let transfer_to = decode_strings(&props, "transfer_to")?;
+        let transfer_types = decode_list(&props, "transfer_types", transfer_from.len())?;
+        let transfer_min_seconds =
+            decode_list(&props, "transfer_min_seconds", transfer_from.len())?;
+        if transfer_to.len() != transfer_from.len() {
+            bail!("transfer_from and transfer_to have different lengths");
+        }
+        let mut transfers = Vec::new();
+        for (idx, (from, to)) in transfer_from.iter().zip(transfer_to).enumerate() {
+            let transfer = match (&transfer_types[idx], &transfer_min_seconds[idx]) {
+                (PropertyValue::UInt8(1), _) => Transfer::Timed,
+                (PropertyValue::UInt8(2), PropertyValue::UInt32(seconds)) => {
+                    Transfer::MinTime(Duration::from_secs((*seconds).into()))
+                }
+                (PropertyValue::UInt8(3), _) => Transfer::Forbidden,
+                _ => bail!("wrong inner type inside transfer_types or transfer_min_seconds"),
+            };
+            transfers.push((
+                orig_ids::StopID::new(from),
+                orig_ids::StopID::new(&to),
+                transfer,
+            ));
+        }
+
+        let trip_ids: Vec<orig_ids::TripID> = decode_strings(&props, "trip_ids")?
+            .iter()
+            .map(|id| orig_ids::TripID::new(id))
+            .collect();
+        let num_trips = trip_ids.len();
+
+        let mut trip_services = Vec::new();
+        for idx in decode_list(&props, "trip_services", num_trips)? {
+            let PropertyValue::UInt32(idx) = idx else {
+                bail!("wrong inner type inside trip_services");
             };
-            for t in raw_times {
-                let PropertyValue::UInt32(seconds) = t else {
+            let idx = *idx as usize;
+            if idx >= services.len() {
+                bail!("trip_services refers to an unknown service");
+            }
+            trip_services.push(idx);
+        }
+        let trip_wheelchair = decode_wheelchair(&props, "trip_wheelchair", num_trips)?;
+
+        let raw_trips = decode_list(&props, "trips", num_trips)?;
+        let raw_dwells = decode_list(&props, "dwells", num_trips)?;
+        let mut trips = Vec::new();
+        for (raw_deltas, raw_dwells) in raw_trips.iter().zip(raw_dwells) {
+            let (PropertyValue::Vec(raw_deltas), PropertyValue::Vec(raw_dwells)) =
+                (raw_deltas, raw_dwells)
+            else {
+                bail!("wrong inner type inside trips or dwells");
+            };
+            if raw_deltas.len() != stop_info.len()
+                || !(raw_dwells.is_empty() || raw_dwells.len() == raw_deltas.len())
+            {
+                bail!("a trip has the wrong number of times or dwells");
+            }
+
+            let mut times = Vec::new();
+            let mut prev_departure = 0;
+            for (idx, delta) in raw_deltas.iter().enumerate() {
+                let PropertyValue::Int32(delta) = delta else {
                     bail!("wrong inner type inside trips");
                 };
-                times.push(NaiveTime::from_num_seconds_from_midnight_opt(*seconds, 0).unwrap());
+                let dwell = match raw_dwells.get(idx) {
+                    Some(PropertyValue::UInt32(dwell)) => *dwell,
+                    Some(_) => bail!("wrong inner type inside dwells"),
+                    None => 0,
+                };
+                let Ok(arrival) = u32::try_from(i64::from(prev_departure) + i64::from(*delta))
+                else {
+                    bail!("a trip has a negative time");
+                };
+                prev_departure = arrival + dwell;
+                times.push((
+                    ServiceTime::from_seconds(arrival),
+                    ServiceTime::from_seconds(prev_departure),
+                ));
             }
             trips.push(times);
         }
 
+        let Some(PropertyValue::Vec(raw_shapes)) = props.get("shapes") else {
+            bail!("shapes missing or wrong type");
+        };
+        let raw_fractions = decode_list(&props, "shape_stop_fractions", raw_shapes.len())?;
+        let mut shapes = Vec::new();
+        for (raw_coords, raw_fractions) in raw_shapes.iter().zip(raw_fractions) {
+            let coords = decode_f64s(raw_coords, "shapes")?;
+            let stop_fractions = decode_f64s(raw_fractions, "shape_stop_fractions")?;
+            if coords.len() % 2 != 0 || stop_fractions.len() != stop_info.len() {
+                bail!("shapes have the wrong length");
+            }
+            shapes.push(Shape {
+                linestring: LineString::new(
+                    coords
+                        .chunks(2)
+                        .map(|pair| Coord {
+                            x: pair[0],
+                            y: pair[1],
+                        })
+                        .collect(),
+                ),
+                stop_fractions,
+            });
+        }
+        let trip_shapes = decode_optional_indices(&props, "trip_shapes", num_trips, shapes.len())?;
+
         Ok(Self {
             stop_info,
             route,
+            services,
+            transfers,
             trips,
+            trip_ids,
+            trip_services,
+            trip_wheelchair,
+            stop_wheelchair,
+            stop_stations,
+            stations,
+            trip_shapes,
+            shapes,
         })
     }
 }
@@ -234,35 +1021,313 @@ fn group_variants(gtfs: &GtfsModel) -> Vec<(RouteVariant, LineString)> {
     use std::collections::BTreeMap;
 
     let mut variants: BTreeMap<Vec<StopID>, (RouteVariant, LineString)> = BTreeMap::new();
+    // Per variant, the shapes used so far
+    let mut variant_shapes: BTreeMap<Vec<StopID>, Vec<ShapeID>> = BTreeMap::new();
 
     for trip in &gtfs.trips {
-        let stop_sequence: Vec<StopID> = trip.stop_sequence.iter().map(|(s, _)| *s).collect();
-        let trip_times = trip.stop_sequence.iter().map(|(_, t)| *t).collect();
+        let stop_sequence: Vec<StopID> = trip.stop_sequence.iter().map(|st| st.stop).collect();
+        let trip_times = trip
+            .stop_sequence
+            .iter()
+            .map(|st| (st.arrival, st.departure))
+            .collect();
 
-        variants
+        let variant = &mut variants
             .entry(stop_sequence.clone())
             .or_insert_with(|| {
                 let mut stop_info = Vec::new();
+                let mut stop_wheelchair = Vec::new();
+                let mut stop_stations = Vec::new();
+                let mut stations: Vec<StationID> = Vec::new();
                 let mut pts = Vec::new();
                 for s in &stop_sequence {
                     let stop = &gtfs.stops[s.0];
                     stop_info.push((stop.orig_id.clone(), stop.name.clone()));
+                    stop_wheelchair.push(stop.wheelchair_boarding);
+                    stop_stations.push(stop.station.map(|station| {
+                        match stations.iter().position(|s| *s == station) {
+                            Some(idx) => idx,
+                            None => {
+                                stations.push(station);
+                                stations.len() - 1
+                            }
+                        }
+                    }));
                     pts.push(stop.point.into());
                 }
+                let stations = stations
+                    .into_iter()
+                    .map(|id| {
+                        let station = &gtfs.stations[id.0];
+                        VariantStation {
+                            orig_id: station.orig_id.clone(),
+                            name: station.name.clone(),
+                            point: station.point,
+                            entrances: station
+                                .entrances
+                                .iter()
+                                .map(|e| VariantEntrance {
+                                    orig_id: e.orig_id.clone(),
+                                    name: e.name.clone(),
+                                    point: e.point,
+                                    wheelchair_boarding: e.wheelchair_boarding,
+                                    platforms: e
+                                        .platforms
+                                        .iter()
+                                        .map(|(stop, enter, exit)| {
+                                            (gtfs.stops[stop.0].orig_id.clone(), *enter, *exit)
+                                        })
+                                        .collect(),
+                                })
+                                .collect(),
+                        }
+                    })
+                    .collect();
+
+                let transfers = gtfs
+                    .transfers
+                    .iter()
+                    .filter(|((from, _), _)| stop_sequence.contains(from))
+                    .map(|((from, to), transfer)| {
+                        (
+                            gtfs.stops[from.0].orig_id.clone(),
+                            gtfs.stops[to.0].orig_id.clone(),
+                            *transfer,
+                        )
+                    })
+                    .collect();
 
                 (
                     RouteVariant {
                         stop_info,
                         trips: Vec::new(),
+                        trip_ids: Vec::new(),
+                        trip_services: Vec::new(),
+                        trip_wheelchair: Vec::new(),
+                        stop_wheelchair,
+                        stop_stations,
+                        stations,
+                        trip_shapes: Vec::new(),
+                        shapes: Vec::new(),
                         route: gtfs.routes[trip.route.0].clone(),
+                        services: Vec::new(),
+                        transfers,
                     },
                     LineString::new(pts),
                 )
             })
-            .0
-            .trips
-            .push(trip_times);
+            .0;
+
+        // Only include the services actually used by this variant
+        let service = &gtfs.services[trip.service.0];
+        let service_idx = match variant
+            .services
+            .iter()
+            .position(|s| s.orig_id == service.orig_id)
+        {
+            Some(idx) => idx,
+            None => {
+                variant.services.push(service.clone());
+                variant.services.len() - 1
+            }
+        };
+        variant.trips.push(trip_times);
+        variant.trip_ids.push(trip.orig_id.clone());
+        variant.trip_services.push(service_idx);
+        variant.trip_wheelchair.push(trip.wheelchair_accessible);
+
+        let shape_idx = trip.shape.map(|shape| {
+            let used = variant_shapes.entry(stop_sequence.clone()).or_default();
+            match used.iter().position(|s| *s == shape) {
+                Some(idx) => idx,
+                None => {
+                    used.push(shape);
+                    variant.shapes.push(gtfs.shapes[shape.0].clone());
+                    used.len() - 1
+                }
+            }
+        });
+        variant.trip_shapes.push(shape_idx);
     }
 
     variants.into_values().collect()
 }
+
+fn decode_f64s(value: &PropertyValue, name: &str) -> Result<Vec<f64>> {
+    let PropertyValue::Vec(list) = value else {
+        bail!("wrong inner type inside {name}");
+    };
+    let mut result = Vec::new();
+    for x in list {
+        let PropertyValue::Float64(x) = x else {
+            bail!("wrong inner type inside {name}");
+        };
+        result.push(*x);
+    }
+    Ok(result)
+}
+
+/// WGS84 points are stored as a flat list of longitude, latitude pairs
+#[cfg(not(target_arch = "wasm32"))]
+fn encode_points(list: impl Iterator<Item = Point>) -> PropertyValue {
+    PropertyValue::Vec(
+        list.flat_map(|pt| {
+            [
+                PropertyValue::Float64(pt.x()),
+                PropertyValue::Float64(pt.y()),
+            ]
+        })
+        .collect(),
+    )
+}
+
+fn decode_points(value: &PropertyValue, name: &str) -> Result<Vec<Point>> {
+    let coords = decode_f64s(value, name)?;
+    if coords.len() % 2 != 0 {
+        bail!("{name} has the wrong length");
+    }
+    Ok(coords
+        .chunks(2)
+        .map(|pair| Point::new(pair[0], pair[1]))
+        .collect())
+}
+
+/// 0 means nothing, otherwise it's 1 + the index
+#[cfg(not(target_arch = "wasm32"))]
+fn encode_optional_indices(list: &[Option<usize>]) -> PropertyValue {
+    PropertyValue::Vec(
+        list.iter()
+            .map(|idx| PropertyValue::UInt32(idx.map(|idx| idx as u32 + 1).unwrap_or(0)))
+            .collect(),
+    )
+}
+
+/// The opposite of `encode_optional_indices`, checking each index is below `max`
+fn decode_optional_indices(
+    props: &Properties,
+    name: &str,
+    len: usize,
+    max: usize,
+) -> Result<Vec<Option<usize>>> {
+    let mut result = Vec::new();
+    for raw in decode_list(props, name, len)? {
+        let PropertyValue::UInt32(idx) = raw else {
+            bail!("wrong inner type inside {name}");
+        };
+        if *idx as usize > max {
+            bail!("{name} refers to something unknown");
+        }
+        result.push(idx.checked_sub(1).map(|idx| idx as usize));
+    }
+    Ok(result)
+}
+
+/// Uses the GTFS encoding: 0 for unknown, 1 for yes, 2 for no
+#[cfg(not(target_arch = "wasm32"))]
+fn encode_wheelchair(list: &[Option<bool>]) -> PropertyValue {
+    PropertyValue::Vec(
+        list.iter()
+            .map(|x| {
+                PropertyValue::UInt8(match x {
+                    None => 0,
+                    Some(true) => 1,
+                    Some(false) => 2,
+                })
+            })
+            .collect(),
+    )
+}
+
+fn decode_wheelchair(props: &Properties, name: &str, len: usize) -> Result<Vec<Option<bool>>> {
+    let mut result = Vec::new();
+    for x in decode_list(props, name, len)? {
+        result.push(match x {
+            PropertyValue::UInt8(0) => None,
+            PropertyValue::UInt8(1) => Some(true),
+            PropertyValue::UInt8(2) => Some(false),
+            _ => bail!("wrong inner type inside {name}"),
+        });
+    }
+    Ok(result)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn encode_strings<'a>(list: impl Iterator<Item = &'a str>) -> PropertyValue {
+    PropertyValue::Vec(list.map(|x| PropertyValue::String(x.to_string())).collect())
+}
+
+fn decode_strings(props: &Properties, name: &str) -> Result<Vec<String>> {
+    let Some(PropertyValue::Vec(list)) = props.get(name) else {
+        bail!("{name} missing or wrong type");
+    };
+    let mut result = Vec::new();
+    for x in list {
+        let PropertyValue::String(x) = x else {
+            bail!("wrong inner type inside {name}");
+        };
+        result.push(x.clone());
+    }
+    Ok(result)
+}
+
+/// Dates are stored as the number of days since 1 January of year 1
+#[cfg(not(target_arch = "wasm32"))]
+fn encode_dates<'a>(list: impl Iterator<Item = &'a NaiveDate>) -> PropertyValue {
+    use chrono::Datelike;
+
+    PropertyValue::Vec(
+        list.map(|date| PropertyValue::Int32(date.num_days_from_ce()))
+            .collect(),
+    )
+}
+
+fn decode_dates(value: &PropertyValue, name: &str) -> Result<Vec<NaiveDate>> {
+    let PropertyValue::Vec(list) = value else {
+        bail!("wrong inner type inside {name}");
+    };
+    let mut result = Vec::new();
+    for x in list {
+        let Some(date) = (match x {
+            PropertyValue::Int32(days) => NaiveDate::from_num_days_from_ce_opt(*days),
+            _ => None,
+        }) else {
+            bail!("wrong inner type inside {name}");
+        };
+        result.push(date);
+    }
+    Ok(result)
+}
+
+/// Gets a list that must have one entry per stop, trip, etc
+fn decode_list<'a>(props: &'a Properties, name: &str, len: usize) -> Result<&'a [PropertyValue]> {
+    let Some(PropertyValue::Vec(list)) = props.get(name) else {
+        bail!("{name} missing or wrong type");
+    };
+    if list.len() != len {
+        bail!("{name} has the wrong length");
+    }
+    Ok(list)
+}
+
+/// Finds each run of consecutive stops that were kept. Runs with just one stop are useless.
+fn kept_ranges(stop_ids: &[Option<StopID>]) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = None;
+    for (idx, stop) in stop_ids.iter().enumerate() {
+        match (stop, start) {
+            (Some(_), None) => {
+                start = Some(idx);
+            }
+            (None, Some(from)) => {
+                ranges.push(from..idx);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(from) = start {
+        ranges.push(from..stop_ids.len());
+    }
+    ranges.retain(|r| r.len() > 1);
+    ranges
+}
@@ -7,17 +7,38 @@ use serde::{Deserialize, Serialize};
 pub mod orig_ids {
     use serde::{Deserialize, Serialize};
 
-    #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
-    pub struct StopID(String);
-
-    #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
-    pub struct TripID(String);
+    // When combining feeds, IDs from different agencies might collide. Keep them separate by
+    // prefixing with a namespace.
+    macro_rules! orig_id {
+        ($name:ident) => {
+            #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+            pub struct $name(String);
+
+            impl $name {
+                pub(crate) fn new(id: &str) -> Self {
+                    Self(id.to_string())
+                }
+
+                pub(crate) fn as_str(&self) -> &str {
+                    &self.0
+                }
+
+                pub fn namespaced(&self, namespace: &str) -> Self {
+                    Self(format!("{namespace}:{}", self.0))
+                }
+            }
+        };
+    }
 
-    #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
-    pub struct ServiceID(String);
+    orig_id!(StopID);
+    orig_id!(TripID);
+    orig_id!(ServiceID);
+    orig_id!(RouteID);
+    orig_id!(AgencyID);
 
+    // Shapes are only matched up while parsing one feed, so they never need a namespace
     #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
-    pub struct RouteID(String);
+    pub struct ShapeID(String);
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
@@ -29,6 +50,18 @@ pub struct TripID(pub usize);
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct RouteID(pub usize);
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ServiceID(pub usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ShapeID(pub usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct StationID(pub usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct PatternID(pub usize);
+
 impl CheapID for StopID {
     fn new(x: usize) -> Self {
         Self(x)
@@ -44,6 +77,26 @@ impl CheapID for RouteID {
         Self(x)
     }
 }
+impl CheapID for ServiceID {
+    fn new(x: usize) -> Self {
+        Self(x)
+    }
+}
+impl CheapID for ShapeID {
+    fn new(x: usize) -> Self {
+        Self(x)
+    }
+}
+impl CheapID for StationID {
+    fn new(x: usize) -> Self {
+        Self(x)
+    }
+}
+impl CheapID for PatternID {
+    fn new(x: usize) -> Self {
+        Self(x)
+    }
+}
 
 pub trait CheapID: Copy {
     fn new(x: usize) -> Self;
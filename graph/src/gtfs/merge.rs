@@ -0,0 +1,183 @@
+use std::collections::BTreeSet;
+
+use super::{FareRule, GtfsModel, RouteID, ShapeID, StationID, StopID, TripID};
+
+impl GtfsModel {
+    /// Combines several feeds into one. When there's more than one feed, the original IDs of
+    /// stops, stations, trips, routes, agencies, and services are prefixed by the index of their
+    /// feed, so IDs reused by different agencies don't collide.
+    pub fn merge(mut feeds: Vec<GtfsModel>) -> GtfsModel {
+        if feeds.len() <= 1 {
+            return feeds.pop().unwrap_or_else(GtfsModel::empty);
+        }
+        let mut result = GtfsModel::empty();
+        for (idx, feed) in feeds.into_iter().enumerate() {
+            result.append(feed, &idx.to_string());
+        }
+        result
+    }
+
+    fn append(&mut self, feed: GtfsModel, namespace: &str) {
+        let stop_offset = self.stops.len();
+        let trip_offset = self.trips.len();
+        let route_offset = self.routes.len();
+        let service_offset = self.services.len();
+        let shape_offset = self.shapes.len();
+        let station_offset = self.stations.len();
+        let pattern_offset = self.patterns.len();
+        let stop_id = |s: StopID| StopID(s.0 + stop_offset);
+        let route_id = |r: RouteID| RouteID(r.0 + route_offset);
+
+        for mut stop in feed.stops {
+            stop.orig_id = stop.orig_id.namespaced(namespace);
+            stop.station = stop.station.map(|s| StationID(s.0 + station_offset));
+            for departures in &mut stop.departures {
+                departures.pattern.0 += pattern_offset;
+                for trip in &mut departures.trips {
+                    trip.0 += trip_offset;
+                }
+            }
+            self.stops.push(stop);
+        }
+
+        for mut trip in feed.trips {
+            trip.orig_id = trip.orig_id.namespaced(namespace);
+            for st in &mut trip.stop_sequence {
+                st.stop = stop_id(st.stop);
+            }
+            trip.route = route_id(trip.route);
+            trip.service.0 += service_offset;
+            trip.shape = trip.shape.map(|s| ShapeID(s.0 + shape_offset));
+            self.trips.push(trip);
+        }
+
+        for mut route in feed.routes {
+            route.orig_id = route.orig_id.namespaced(namespace);
+            if let Some(ref mut agency) = route.agency {
+                agency.orig_id = agency.orig_id.namespaced(namespace);
+            }
+            self.routes.push(route);
+        }
+        for mut service in feed.services {
+            service.orig_id = service.orig_id.namespaced(namespace);
+            self.services.push(service);
+        }
+        self.shapes.extend(feed.shapes);
+
+        for mut pattern in feed.patterns {
+            pattern.route = route_id(pattern.route);
+            for stop in &mut pattern.stops {
+                *stop = stop_id(*stop);
+            }
+            for trip in &mut pattern.trips {
+                *trip = TripID(trip.0 + trip_offset);
+            }
+            self.patterns.push(pattern);
+        }
+
+        for mut station in feed.stations {
+            station.orig_id = station.orig_id.namespaced(namespace);
+            for stop in &mut station.platforms {
+                *stop = stop_id(*stop);
+            }
+            for entrance in &mut station.entrances {
+                entrance.orig_id = entrance.orig_id.namespaced(namespace);
+                for (stop, _, _) in &mut entrance.platforms {
+                    *stop = stop_id(*stop);
+                }
+            }
+            self.stations.push(station);
+        }
+
+        for ((from, to), transfer) in feed.transfers {
+            self.transfers
+                .insert((stop_id(from), stop_id(to)), transfer);
+        }
+
+        let remap_stops =
+            |stops: Option<BTreeSet<StopID>>| stops.map(|x| x.into_iter().map(stop_id).collect());
+        for rule in feed.fares {
+            self.fares.push(FareRule {
+                routes: rule.routes.map(|x| x.into_iter().map(route_id).collect()),
+                from_stops: remap_stops(rule.from_stops),
+                to_stops: remap_stops(rule.to_stops),
+                ..rule
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::fixture;
+    use crate::{ServiceTime, TransitOptions};
+
+    #[test]
+    fn namespaced_ids() {
+        // Both feeds use stops A and B and agency A1
+        let gtfs = GtfsModel::merge(vec![fixture::gtfs("raptor"), fixture::gtfs("frequencies")]);
+
+        let stops: Vec<&str> = gtfs.stops.iter().map(|s| s.orig_id.as_str()).collect();
+        assert_eq!(stops, vec!["0:A", "0:B", "0:C", "1:A", "1:B"]);
+        let agencies: Vec<&str> = gtfs
+            .routes
+            .iter()
+            .map(|r| r.agency.as_ref().unwrap().orig_id.as_str())
+            .collect();
+        assert_eq!(agencies, vec!["0:A1", "0:A1", "0:A1", "1:A1"]);
+
+        // Trips still refer to stops, routes, and services from their own feed
+        for trip in &gtfs.trips {
+            let namespace = &trip.orig_id.as_str()[..2];
+            assert!(trip.stop_sequence.iter().all(|st| {
+                gtfs.stops[st.stop.0]
+                    .orig_id
+                    .as_str()
+                    .starts_with(namespace)
+            }));
+            assert!(
+                gtfs.routes[trip.route.0]
+                    .orig_id
+                    .as_str()
+                    .starts_with(namespace)
+            );
+            assert!(
+                gtfs.services[trip.service.0]
+                    .orig_id
+                    .as_str()
+                    .starts_with(namespace)
+            );
+        }
+
+        // The timetable points at the right trips
+        let options = TransitOptions::new(NaiveDate::from_ymd_opt(2024, 3, 4).unwrap());
+        let next_steps = gtfs.trips_from(
+            StopID(3),
+            ServiceTime::from_hms(8, 10, 0).unwrap(),
+            Duration::from_secs(30 * 60),
+            &options,
+        );
+        let trips: Vec<(&str, String)> = next_steps
+            .iter()
+            .map(|step| {
+                (
+                    gtfs.trips[step.trip.0].orig_id.as_str(),
+                    step.time1.to_string(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            trips,
+            vec![
+                ("1:EVERY", "08:20:00".to_string()),
+                ("1:EVERY", "08:40:00".to_string())
+            ]
+        );
+        assert!(next_steps.iter().all(|step| step.stop2 == StopID(4)));
+    }
+}
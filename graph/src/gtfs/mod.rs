@@ -1,55 +1,183 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::time::Duration;
 
-use chrono::NaiveTime;
-use geo::Point;
+use chrono::{Days, NaiveDate};
+use geo::{LineString, Point};
 use geojson::{Feature, Geometry};
 use serde::{Deserialize, Serialize};
 use utils::Mercator;
 
+pub use self::area::StudyArea;
+pub use self::calendar::Service;
+pub use self::fares::{FareRule, FareSoFar};
 use self::ids::orig_ids;
-pub use self::ids::{RouteID, StopID, TripID};
-use crate::RoadID;
+pub use self::ids::{PatternID, RouteID, ServiceID, ShapeID, StationID, StopID, TripID};
+pub use self::mode::TransitMode;
+pub use self::time::ServiceTime;
+pub use self::timetable::{Departures, Pattern};
+pub use self::transfers::{Alighting, Transfer};
+pub use self::validate::GtfsIssue;
+use crate::Position;
 
+mod area;
+mod calendar;
+mod fares;
+mod files;
 #[cfg(feature = "gtfs")]
 mod gmd;
 mod ids;
+mod merge;
+mod mode;
+mod realtime;
+mod remove;
 mod scrape;
-
-// TODO days of the week, exceptions, etc. a daily model for now.
+mod shapes;
+mod stations;
+mod time;
+mod timetable;
+mod transfers;
+mod validate;
+mod write;
 
 #[derive(Serialize, Deserialize)]
 pub struct GtfsModel {
-    // Indexed by StopID and TripID
+    // Indexed by StopID, TripID, RouteID, ServiceID, ShapeID, StationID, and PatternID
     pub stops: Vec<Stop>,
     pub trips: Vec<Trip>,
     pub routes: Vec<Route>,
+    pub services: Vec<Service>,
+    pub shapes: Vec<Shape>,
+    pub stations: Vec<Station>,
+    /// Rules about changing trips from the first stop to the second
+    pub transfers: BTreeMap<(StopID, StopID), Transfer>,
+    pub fares: Vec<FareRule>,
+    /// Derived from trips
+    pub patterns: Vec<Pattern>,
+}
+
+/// Settings for searches using public transit
+#[derive(Clone)]
+pub struct TransitOptions {
+    /// Only trips running on this date are used
+    pub date: NaiveDate,
+    /// The time needed to change between trips, unless `transfers.txt` says otherwise
+    pub min_transfer_time: Duration,
+    /// If specified, only routes with these modes are used
+    pub modes: Option<BTreeSet<TransitMode>>,
+    /// If specified, don't take trips making the total fare more than this. Only makes sense if
+    /// `GtfsModel::fare_currency` succeeds.
+    pub max_fare: Option<f64>,
+    /// Only board wheelchair-accessible trips at accessible stops, get off at accessible stops,
+    /// and use step-free entrances. Unknown accessibility counts as inaccessible.
+    pub wheelchair: bool,
 }
 
+impl TransitOptions {
+    pub fn new(date: NaiveDate) -> Self {
+        Self {
+            date,
+            min_transfer_time: Duration::from_secs(2 * 60),
+            modes: None,
+            max_fare: None,
+            wheelchair: false,
+        }
+    }
+}
+
+/// Where vehicles stop. In a station, this is a platform.
 #[derive(Serialize, Deserialize)]
 pub struct Stop {
     pub name: String,
     pub orig_id: orig_ids::StopID,
     pub point: Point,
-    pub road: RoadID,
-    // Sorted by time1
-    pub next_steps: Vec<NextStep>,
+    pub station: Option<StationID>,
+    /// Can somebody in a wheelchair board here? `None` if unknown.
+    pub wheelchair_boarding: Option<bool>,
+    /// How to walk between the road network and this stop. Filled out by snapping.
+    pub access: Vec<StopAccess>,
+    /// Per pattern visiting this stop, when trips leave
+    pub departures: Vec<Departures>,
 }
 
-// TODO Detangle and make it more clear what's serialized and what's derived
-/// `trip` arrives at some `Stop` at `time`. Then it reaches `stop2` at `time2`
+/// A way to walk between a road and a stop, possibly through a station entrance
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct StopAccess {
+    /// Where the stop or entrance is along the road
+    pub position: Position,
+    /// The time to walk from the road to the stop
+    pub enter: Duration,
+    /// The time to walk from the stop to the road
+    pub exit: Duration,
+    /// Direct access to the stop, or through a wheelchair-accessible entrance
+    pub step_free: bool,
+}
+
+/// A station groups together platforms, which may be reached from the street through entrances
 #[derive(Serialize, Deserialize)]
+pub struct Station {
+    pub name: String,
+    pub orig_id: orig_ids::StopID,
+    pub point: Point,
+    pub platforms: Vec<StopID>,
+    pub entrances: Vec<Entrance>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Entrance {
+    pub name: String,
+    pub orig_id: orig_ids::StopID,
+    pub point: Point,
+    /// Is this entrance usable by somebody in a wheelchair? `None` if unknown.
+    pub wheelchair_boarding: Option<bool>,
+    /// For each platform reachable from here, the time to walk there and the time to walk back.
+    /// This uses `pathways.txt` if possible, or the straight-line distance otherwise.
+    pub platforms: Vec<(StopID, Duration, Duration)>,
+}
+
+// TODO Detangle and make it more clear what's serialized and what's derived
+/// `trip` departs some `Stop` at `time1`. Then it arrives at `stop2` at `time2`. Times are relative
+/// to the start of the trip's service day.
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct NextStep {
-    pub time1: NaiveTime,
+    pub time1: ServiceTime,
     pub trip: TripID,
+    pub pattern: PatternID,
     pub stop2: StopID,
-    pub time2: NaiveTime,
+    pub time2: ServiceTime,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Trip {
-    // (stop, arrival time) in order
-    pub stop_sequence: Vec<(StopID, NaiveTime)>,
+    /// Trips from `frequencies.txt` or split at the edge of the study area share this
+    pub orig_id: orig_ids::TripID,
+    /// When the whole trip starts, even if its first stops are outside the study area. For trips
+    /// from `frequencies.txt`, this is when this instance starts.
+    pub start_time: ServiceTime,
+    // In order
+    pub stop_sequence: Vec<StopTime>,
     pub route: RouteID,
+    pub service: ServiceID,
+    /// Not all feeds describe the path vehicles take between stops
+    pub shape: Option<ShapeID>,
+    /// Can the vehicle carry a wheelchair? `None` if unknown.
+    pub wheelchair_accessible: Option<bool>,
+}
+
+/// The path vehicles take. This is specific to the stops visited, so every trip using one shape
+/// has the same `stop_sequence`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Shape {
+    pub linestring: LineString,
+    /// For every stop visited, how far along `linestring` it is, from 0 to 1
+    pub stop_fractions: Vec<f64>,
+}
+
+/// When a trip visits a stop. The vehicle may wait between arriving and departing.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct StopTime {
+    pub stop: StopID,
+    pub arrival: ServiceTime,
+    pub departure: ServiceTime,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -58,6 +186,19 @@ pub struct Route {
     pub short_name: Option<String>,
     pub long_name: Option<String>,
     pub description: Option<String>,
+    /// The raw value from GTFS, possibly an extended type. Use `mode` to interpret it.
+    pub route_type: u16,
+    /// Who runs the route. `None` if the feed doesn't say.
+    pub agency: Option<Agency>,
+}
+
+/// From `agency.txt`. Feeds with only one agency may leave out the ID, so the name is used then.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Agency {
+    pub orig_id: orig_ids::AgencyID,
+    pub name: String,
+    pub url: String,
+    pub timezone: String,
 }
 
 impl GtfsModel {
@@ -66,38 +207,166 @@ impl GtfsModel {
             stops: Vec::new(),
             trips: Vec::new(),
             routes: Vec::new(),
+            services: Vec::new(),
+            shapes: Vec::new(),
+            stations: Vec::new(),
+            transfers: BTreeMap::new(),
+            fares: Vec::new(),
+            patterns: Vec::new(),
         }
     }
 
     /// Starting from a stop at some time, find all the next trips going somewhere, waiting up to
-    /// max_wait.
-    pub fn trips_from(&self, stop1: StopID, time: NaiveTime, max_wait: Duration) -> Vec<&NextStep> {
-        // TODO Binary search
+    /// max_wait. Only trips allowed by `options` are considered. `time` is relative to the service
+    /// day on the requested date. Trips from the previous service day still running after
+    /// midnight and trips on the next service day are also considered, with their times shifted
+    /// to be relative to the requested date.
+    pub fn trips_from(
+        &self,
+        stop1: StopID,
+        time: ServiceTime,
+        max_wait: Duration,
+        options: &TransitOptions,
+    ) -> Vec<NextStep> {
+        let date = options.date;
         let mut results = Vec::new();
-        for next_step in &self.stops[stop1.0].next_steps {
-            // These are sorted by time, so give up after we've seen enough
-            if next_step.time1 > time + max_wait {
-                break;
-            }
+        if options.wheelchair && self.stops[stop1.0].wheelchair_boarding != Some(true) {
+            return results;
+        }
+        for days in [-1, 0, 1] {
+            let service_date = match days {
+                -1 => date.checked_sub_days(Days::new(1)),
+                0 => Some(date),
+                _ => date.checked_add_days(Days::new(1)),
+            };
+            // Express the search window relative to that service day
+            let (Some(service_date), Some(end)) =
+                (service_date, (time + max_wait).shift_days(-days))
+            else {
+                continue;
+            };
+            let start = time
+                .shift_days(-days)
+                .unwrap_or(ServiceTime::from_seconds(0));
 
-            if next_step.time1 >= time {
-                results.push(next_step);
+            for departures in &self.stops[stop1.0].departures {
+                let pattern = &self.patterns[departures.pattern.0];
+                if options
+                    .modes
+                    .as_ref()
+                    .is_some_and(|modes| !modes.contains(&self.routes[pattern.route.0].mode()))
+                {
+                    continue;
+                }
+
+                for i in departures.between(start, end) {
+                    let trip = &self.trips[departures.trips[i].0];
+                    if !self.runs_on(trip.service, service_date)
+                        || (options.wheelchair && trip.wheelchair_accessible != Some(true))
+                    {
+                        continue;
+                    }
+                    let mut next_step = self.next_step(departures, i);
+                    if options.wheelchair {
+                        let Some(step) = self.skip_to_accessible_stop(&next_step) else {
+                            continue;
+                        };
+                        next_step = step;
+                    }
+                    results.push(NextStep {
+                        time1: next_step.time1.shift_days(days).unwrap(),
+                        trip: next_step.trip,
+                        pattern: next_step.pattern,
+                        stop2: next_step.stop2,
+                        time2: next_step.time2.shift_days(days).unwrap(),
+                    });
+                }
             }
         }
         results
     }
-}
 
-impl Stop {
-    pub fn to_gj(&self, mercator: &Mercator) -> Feature {
-        let mut f = Feature::from(Geometry::from(&mercator.to_wgs84(&self.point)));
-        f.set_property("name", self.name.clone());
+    pub fn stop_to_gj(&self, stop: StopID, mercator: &Mercator) -> Feature {
+        let s = &self.stops[stop.0];
+        let mut f = Feature::from(Geometry::from(&mercator.to_wgs84(&s.point)));
+        f.set_property("name", s.name.clone());
         f.set_property(
             "next_steps",
-            serde_json::to_value(&self.next_steps).unwrap(),
+            serde_json::to_value(self.all_next_steps(stop)).unwrap(),
         );
         f
     }
+
+    /// Wheelchair users can't get off at inaccessible stops, so extend `next_step` to the next
+    /// accessible stop on the same trip.
+    fn skip_to_accessible_stop(&self, next_step: &NextStep) -> Option<NextStep> {
+        let stop_sequence = &self.trips[next_step.trip.0].stop_sequence;
+        // A trip could visit the same stop twice, so match the time too
+        let idx = stop_sequence
+            .iter()
+            .position(|st| st.stop == next_step.stop2 && st.arrival == next_step.time2)?;
+        let st = stop_sequence[idx..]
+            .iter()
+            .find(|st| self.stops[st.stop.0].wheelchair_boarding == Some(true))?;
+        Some(NextStep {
+            time1: next_step.time1,
+            trip: next_step.trip,
+            pattern: next_step.pattern,
+            stop2: st.stop,
+            time2: st.arrival,
+        })
+    }
+
+    /// After riding `next_step`, staying on the same vehicle reaches these later stops, in order.
+    /// Each step still starts where `next_step` was boarded. Like `trips_from`, stops that
+    /// `options` don't allow getting off at are skipped. Later stops are only looked up as needed,
+    /// so callers can stop early.
+    pub fn stay_on<'a>(
+        &'a self,
+        next_step: &NextStep,
+        options: &'a TransitOptions,
+    ) -> impl Iterator<Item = NextStep> + 'a {
+        let stop_sequence = &self.trips[next_step.trip.0].stop_sequence;
+        // The times may have been shifted to another service day. A trip could visit the same stop
+        // twice, so match the time too.
+        let (days, later) = match [0, -1, 1].into_iter().find_map(|days| {
+            let time2 = next_step.time2.shift_days(-days)?;
+            let idx = stop_sequence
+                .iter()
+                .position(|st| st.stop == next_step.stop2 && st.arrival == time2)?;
+            Some((days, idx))
+        }) {
+            Some((days, idx)) => (days, &stop_sequence[idx + 1..]),
+            None => (0, &stop_sequence[..0]),
+        };
+        let next_step = *next_step;
+        later
+            .iter()
+            .filter(|st| {
+                !options.wheelchair || self.stops[st.stop.0].wheelchair_boarding == Some(true)
+            })
+            .filter_map(move |st| {
+                Some(NextStep {
+                    time1: next_step.time1,
+                    trip: next_step.trip,
+                    pattern: next_step.pattern,
+                    stop2: st.stop,
+                    time2: st.arrival.shift_days(days)?,
+                })
+            })
+    }
+}
+
+impl Stop {
+    /// The ways to walk between this stop and roads that `options` allows
+    pub fn usable_access<'a>(
+        &'a self,
+        options: &'a TransitOptions,
+    ) -> impl Iterator<Item = &'a StopAccess> {
+        self.access
+            .iter()
+            .filter(|a| !options.wheelchair || a.step_free)
+    }
 }
 
 impl Route {
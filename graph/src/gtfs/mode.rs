@@ -0,0 +1,50 @@
+use serde::{Deserialize, Serialize};
+
+use super::Route;
+
+/// A simplified grouping of GTFS route types, for filtering which routes a search can use
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum TransitMode {
+    Tram,
+    Metro,
+    Rail,
+    Bus,
+    Coach,
+    Ferry,
+    /// Aerial lifts and funiculars
+    Cable,
+    Other,
+}
+
+impl TransitMode {
+    /// Handles both the basic route types from the GTFS spec and the extended types from
+    /// <https://developers.google.com/transit/gtfs/reference/extended-route-types>
+    pub fn from_route_type(route_type: u16) -> Self {
+        match route_type {
+            // Cable trams run on streets like trams
+            0 | 5 => Self::Tram,
+            // Monorail
+            1 | 12 => Self::Metro,
+            2 => Self::Rail,
+            // Trolleybus
+            3 | 11 => Self::Bus,
+            4 => Self::Ferry,
+            6 | 7 => Self::Cable,
+
+            100..=199 => Self::Rail,
+            200..=299 => Self::Coach,
+            400..=499 => Self::Metro,
+            700..=899 => Self::Bus,
+            900..=999 => Self::Tram,
+            1000..=1099 | 1200..=1299 => Self::Ferry,
+            1300..=1499 => Self::Cable,
+            _ => Self::Other,
+        }
+    }
+}
+
+impl Route {
+    pub fn mode(&self) -> TransitMode {
+        TransitMode::from_route_type(self.route_type)
+    }
+}
@@ -0,0 +1,428 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use chrono::{Datelike, NaiveDate};
+use prost::Message;
+
+use super::{GtfsModel, Service, ServiceID, ServiceTime, Shape, ShapeID, Trip, TripID, orig_ids};
+
+impl GtfsModel {
+    /// Like `apply_realtime`, but reads a GTFS-Realtime feed from a file.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn apply_realtime_file(&mut self, path: &str) -> Result<()> {
+        let bytes = std::fs::read(path)?;
+        self.apply_realtime(&bytes)
+    }
+
+    /// Applies every `TripUpdate` from a GTFS-Realtime feed, delaying trips, cancelling them, and
+    /// removing skipped stops. An update with a `start_date` only changes the trip on that day, by
+    /// splitting off a copy running just then. Without one, the update applies on every day the
+    /// trip runs. For frequency-based trips, `start_time` picks the instance starting then, and
+    /// updates for an instance that doesn't exist are skipped. When feeds have been merged, trip
+    /// IDs in the realtime feed must include the namespace.
+    pub fn apply_realtime(&mut self, bytes: &[u8]) -> Result<()> {
+        let feed = FeedMessage::decode(bytes)?;
+
+        // Frequency-based trips and trips split at the edge of the study area share an ID
+        let mut trips_by_id: BTreeMap<orig_ids::TripID, Vec<TripID>> = BTreeMap::new();
+        for (idx, trip) in self.trips.iter().enumerate() {
+            trips_by_id
+                .entry(trip.orig_id.clone())
+                .or_default()
+                .push(TripID(idx));
+        }
+
+        // Per (original service, date), the services for the rest of the days and just that day
+        let mut split_services: BTreeMap<(ServiceID, NaiveDate), (ServiceID, ServiceID)> =
+            BTreeMap::new();
+
+        let mut updated = 0;
+        let mut cancelled = 0;
+        let mut unknown = 0;
+        let mut unsupported = 0;
+        for update in feed.entity.into_iter().filter_map(|e| e.trip_update) {
+            let Some(descriptor) = update.trip.as_ref() else {
+                unsupported += 1;
+                continue;
+            };
+            let Some(trip_id) = descriptor.trip_id.as_ref() else {
+                unsupported += 1;
+                continue;
+            };
+            let Some(trips) = trips_by_id.get(&orig_ids::TripID::new(trip_id)) else {
+                unknown += 1;
+                continue;
+            };
+            let (Ok(start_time), Ok(start_date)) = (
+                descriptor
+                    .start_time
+                    .as_ref()
+                    .map(|x| ServiceTime::parse(x))
+                    .transpose(),
+                descriptor
+                    .start_date
+                    .as_ref()
+                    .map(|x| NaiveDate::parse_from_str(x, "%Y%m%d"))
+                    .transpose(),
+            ) else {
+                unsupported += 1;
+                continue;
+            };
+
+            // Pieces of a trip split at the edge of the study area keep the original start_time
+            let mut trips: Vec<TripID> = trips
+                .iter()
+                .filter(|t| start_time.is_none_or(|time| self.trips[t.0].start_time == time))
+                .cloned()
+                .collect();
+            if let Some(date) = start_date {
+                trips = trips
+                    .into_iter()
+                    .filter_map(|t| self.split_trip_on(t, date, &mut split_services))
+                    .collect();
+            }
+            if trips.is_empty() {
+                unknown += 1;
+                continue;
+            }
+
+            match descriptor.schedule_relationship {
+                None | Some(TRIP_SCHEDULED) => {
+                    for trip in trips {
+                        self.apply_trip_update(trip, &update);
+                    }
+                    updated += 1;
+                }
+                Some(TRIP_CANCELED) | Some(TRIP_DELETED) => {
+                    for trip in trips {
+                        let trip = &mut self.trips[trip.0];
+                        trip.stop_sequence.clear();
+                        trip.shape = None;
+                    }
+                    cancelled += 1;
+                }
+                // TODO Added, duplicated, and replacement trips aren't in the static timetable
+                Some(_) => {
+                    unsupported += 1;
+                }
+            }
+        }
+        info!(
+            "Applied {updated} trip updates and {cancelled} cancellations. Skipped {unknown} for unknown trips and {unsupported} unsupported updates"
+        );
+
+        self.build_timetable();
+        Ok(())
+    }
+
+    /// Makes `trip` stop running on `date`, and returns a copy running only on that day. Returns
+    /// `None` if the trip doesn't run then.
+    fn split_trip_on(
+        &mut self,
+        trip: TripID,
+        date: NaiveDate,
+        split_services: &mut BTreeMap<(ServiceID, NaiveDate), (ServiceID, ServiceID)>,
+    ) -> Option<TripID> {
+        let service = self.trips[trip.0].service;
+        if !self.runs_on(service, date) {
+            return None;
+        }
+        let (rest, only) = *split_services.entry((service, date)).or_insert_with(|| {
+            let mut rest = self.services[service.0].clone();
+            rest.removed_dates.insert(date);
+            let mut only = Service::new(orig_ids::ServiceID::new(&format!(
+                "{}_{:04}{:02}{:02}",
+                rest.orig_id.as_str(),
+                date.year(),
+                date.month(),
+                date.day()
+            )));
+            only.added_dates.insert(date);
+            self.services.push(rest);
+            self.services.push(only);
+            (
+                ServiceID(self.services.len() - 2),
+                ServiceID(self.services.len() - 1),
+            )
+        });
+
+        let orig = &mut self.trips[trip.0];
+        orig.service = rest;
+        let copy = Trip {
+            orig_id: orig.orig_id.clone(),
+            start_time: orig.start_time,
+            stop_sequence: orig.stop_sequence.clone(),
+            route: orig.route,
+            service: only,
+            shape: orig.shape,
+            wheelchair_accessible: orig.wheelchair_accessible,
+        };
+        self.trips.push(copy);
+        Some(TripID(self.trips.len() - 1))
+    }
+
+    fn apply_trip_update(&mut self, trip_id: TripID, update: &TripUpdate) {
+        let trip = &mut self.trips[trip_id.0];
+
+        // Match updates to stops in order, since a trip may visit the same stop twice
+        let mut matches: Vec<Option<&StopTimeUpdate>> = vec![None; trip.stop_sequence.len()];
+        let mut start = 0;
+        for stop_update in &update.stop_time_update {
+            // TODO stop_sequence refers to stop_times.txt, which isn't kept
+            let Some(stop_id) = stop_update
+                .stop_id
+                .as_ref()
+                .map(|x| orig_ids::StopID::new(x))
+            else {
+                continue;
+            };
+            let Some(idx) = trip.stop_sequence[start..]
+                .iter()
+                .position(|st| self.stops[st.stop.0].orig_id == stop_id)
+            else {
+                continue;
+            };
+            matches[start + idx] = Some(stop_update);
+            start += idx + 1;
+        }
+
+        // A delay carries on to later stops, until another update says otherwise. The trip-wide
+        // delay applies to stops before the first update.
+        let mut delay = i64::from(update.delay.unwrap_or(0));
+        let mut keep = Vec::new();
+        let mut prev_departure = None;
+        for (st, stop_update) in trip.stop_sequence.iter_mut().zip(matches) {
+            let mut arrival_delay = delay;
+            let mut departure_delay = delay;
+            if let Some(stop_update) = stop_update {
+                match stop_update.schedule_relationship {
+                    Some(STOP_SKIPPED) => {
+                        keep.push(false);
+                        continue;
+                    }
+                    Some(STOP_NO_DATA) => {
+                        delay = 0;
+                        arrival_delay = 0;
+                        departure_delay = 0;
+                    }
+                    _ => {
+                        // TODO Absolute times need the agency's timezone
+                        let arrival = stop_update.arrival.as_ref().and_then(|e| e.delay);
+                        let departure = stop_update.departure.as_ref().and_then(|e| e.delay);
+                        if let Some(x) = arrival.or(departure) {
+                            arrival_delay = x.into();
+                        }
+                        if let Some(x) = departure.or(arrival) {
+                            departure_delay = x.into();
+                        }
+                        delay = departure_delay;
+                    }
+                }
+            }
+            keep.push(true);
+
+            // Delays could make times go backwards, so don't let a vehicle leave before arriving
+            st.arrival = shift(st.arrival, arrival_delay);
+            if let Some(prev) = prev_departure {
+                st.arrival = st.arrival.max(prev);
+            }
+            st.departure = shift(st.departure, departure_delay).max(st.arrival);
+            prev_departure = Some(st.departure);
+        }
+
+        if keep.iter().all(|x| *x) {
+            return;
+        }
+        let mut idx = 0;
+        trip.stop_sequence.retain(|_| {
+            idx += 1;
+            keep[idx - 1]
+        });
+        // The shape has a position per stop, so make a copy without the skipped stops
+        if let Some(shape) = trip.shape {
+            let shape = &self.shapes[shape.0];
+            let copy = Shape {
+                linestring: shape.linestring.clone(),
+                stop_fractions: shape
+                    .stop_fractions
+                    .iter()
+                    .zip(&keep)
+                    .filter(|(_, ok)| **ok)
+                    .map(|(x, _)| *x)
+                    .collect(),
+            };
+            self.shapes.push(copy);
+            trip.shape = Some(ShapeID(self.shapes.len() - 1));
+        }
+    }
+}
+
+fn shift(time: ServiceTime, seconds: i64) -> ServiceTime {
+    ServiceTime::from_seconds((i64::from(time.seconds()) + seconds).max(0) as u32)
+}
+
+// The parts of the GTFS-Realtime protobuf schema needed for trip updates. See
+// https://gtfs.org/documentation/realtime/proto/. Other fields are ignored while decoding.
+
+const TRIP_SCHEDULED: i32 = 0;
+const TRIP_CANCELED: i32 = 3;
+const TRIP_DELETED: i32 = 7;
+const STOP_SKIPPED: i32 = 1;
+const STOP_NO_DATA: i32 = 2;
+
+#[derive(Clone, PartialEq, Message)]
+struct FeedMessage {
+    #[prost(message, repeated, tag = "2")]
+    entity: Vec<FeedEntity>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct FeedEntity {
+    #[prost(message, optional, tag = "3")]
+    trip_update: Option<TripUpdate>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct TripUpdate {
+    #[prost(message, optional, tag = "1")]
+    trip: Option<TripDescriptor>,
+    #[prost(message, repeated, tag = "2")]
+    stop_time_update: Vec<StopTimeUpdate>,
+    /// In seconds
+    #[prost(int32, optional, tag = "5")]
+    delay: Option<i32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct TripDescriptor {
+    #[prost(string, optional, tag = "1")]
+    trip_id: Option<String>,
+    /// For frequency-based trips, like "08:15:00"
+    #[prost(string, optional, tag = "2")]
+    start_time: Option<String>,
+    /// Like "20250106"
+    #[prost(string, optional, tag = "3")]
+    start_date: Option<String>,
+    #[prost(int32, optional, tag = "4")]
+    schedule_relationship: Option<i32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct StopTimeUpdate {
+    #[prost(message, optional, tag = "2")]
+    arrival: Option<StopTimeEvent>,
+    #[prost(message, optional, tag = "3")]
+    departure: Option<StopTimeEvent>,
+    #[prost(string, optional, tag = "4")]
+    stop_id: Option<String>,
+    #[prost(int32, optional, tag = "5")]
+    schedule_relationship: Option<i32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct StopTimeEvent {
+    /// In seconds
+    #[prost(int32, optional, tag = "1")]
+    delay: Option<i32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture;
+
+    fn update(trip_id: &str, start_time: Option<&str>, start_date: Option<&str>) -> FeedEntity {
+        FeedEntity {
+            trip_update: Some(TripUpdate {
+                trip: Some(TripDescriptor {
+                    trip_id: Some(trip_id.to_string()),
+                    start_time: start_time.map(|x| x.to_string()),
+                    start_date: start_date.map(|x| x.to_string()),
+                    schedule_relationship: None,
+                }),
+                stop_time_update: vec![StopTimeUpdate {
+                    arrival: Some(StopTimeEvent { delay: Some(600) }),
+                    departure: None,
+                    stop_id: Some("B".to_string()),
+                    schedule_relationship: None,
+                }],
+                delay: Some(60),
+            }),
+        }
+    }
+
+    /// Per trip, the services it runs on and its times
+    fn times(gtfs: &GtfsModel, date: &str) -> Vec<(String, Vec<String>)> {
+        let date = NaiveDate::parse_from_str(date, "%Y%m%d").unwrap();
+        gtfs.trips
+            .iter()
+            .filter(|trip| gtfs.runs_on(trip.service, date))
+            .map(|trip| {
+                (
+                    trip.orig_id.as_str().to_string(),
+                    trip.stop_sequence
+                        .iter()
+                        .map(|st| st.arrival.to_string())
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn shift_one_day() {
+        let mut gtfs = fixture::gtfs("raptor");
+        let feed = FeedMessage {
+            entity: vec![update("FAST1", None, Some("20240304"))],
+        };
+        gtfs.apply_realtime(&feed.encode_to_vec()).unwrap();
+
+        let on = times(&gtfs, "20240304");
+        let off = times(&gtfs, "20240305");
+        let fast1 = |times: &[(String, Vec<String>)]| {
+            times
+                .iter()
+                .filter(|(id, _)| id == "FAST1")
+                .map(|(_, times)| times.clone())
+                .collect::<Vec<_>>()
+        };
+        // The trip-wide delay applies before the first update
+        assert_eq!(fast1(&on), vec![vec!["08:01:00", "08:20:00"]]);
+        assert_eq!(fast1(&off), vec![vec!["08:00:00", "08:10:00"]]);
+        assert_eq!(on.len(), off.len());
+    }
+
+    #[test]
+    fn shift_one_frequency_instance() {
+        let mut gtfs = fixture::gtfs("frequencies");
+        let feed = FeedMessage {
+            entity: vec![update("EVERY", Some("08:20:00"), None)],
+        };
+        gtfs.apply_realtime(&feed.encode_to_vec()).unwrap();
+
+        let starts: Vec<Vec<String>> = times(&gtfs, "20240304")
+            .into_iter()
+            .map(|(_, times)| times)
+            .collect();
+        assert_eq!(
+            starts,
+            vec![
+                vec!["08:00:00", "08:05:00"],
+                vec!["08:21:00", "08:35:00"],
+                vec!["08:40:00", "08:45:00"],
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_frequency_instance() {
+        let mut gtfs = fixture::gtfs("frequencies");
+        let before = times(&gtfs, "20240304");
+        // Instances start every 20 minutes from 08:00, so none start at 08:10
+        let feed = FeedMessage {
+            entity: vec![update("EVERY", Some("08:10:00"), None)],
+        };
+        gtfs.apply_realtime(&feed.encode_to_vec()).unwrap();
+        assert_eq!(times(&gtfs, "20240304"), before);
+    }
+}
@@ -0,0 +1,82 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::{GtfsModel, StopID};
+
+impl GtfsModel {
+    /// Removes stops and every visit trips make to them. Everything referring to the remaining
+    /// stops is updated, and the timetable is rebuilt.
+    pub fn remove_stops(&mut self, remove: &BTreeSet<StopID>) {
+        if remove.is_empty() {
+            return;
+        }
+
+        // Where each stop moves to, if it's kept
+        let mut new_ids: Vec<Option<StopID>> = Vec::new();
+        let mut count = 0;
+        for idx in 0..self.stops.len() {
+            if remove.contains(&StopID(idx)) {
+                new_ids.push(None);
+            } else {
+                new_ids.push(Some(StopID(count)));
+                count += 1;
+            }
+        }
+        let new_id = |s: &StopID| new_ids[s.0];
+
+        let mut idx = 0;
+        self.stops.retain(|_| {
+            idx += 1;
+            new_ids[idx - 1].is_some()
+        });
+
+        // Every trip using a shape visits the same stops, so only fix each shape once
+        let mut fixed_shapes = BTreeSet::new();
+        for trip in &mut self.trips {
+            if let Some(shape) = trip.shape
+                && fixed_shapes.insert(shape)
+            {
+                let mut stops = trip.stop_sequence.iter();
+                self.shapes[shape.0]
+                    .stop_fractions
+                    .retain(|_| stops.next().is_some_and(|st| new_id(&st.stop).is_some()));
+            }
+            // Trips left with fewer than two stops are ignored by the timetable
+            trip.stop_sequence.retain_mut(|st| match new_id(&st.stop) {
+                Some(id) => {
+                    st.stop = id;
+                    true
+                }
+                None => false,
+            });
+        }
+
+        for station in &mut self.stations {
+            station.platforms = station.platforms.iter().filter_map(new_id).collect();
+            for entrance in &mut station.entrances {
+                entrance.platforms = entrance
+                    .platforms
+                    .iter()
+                    .filter_map(|(s, enter, exit)| Some((new_id(s)?, *enter, *exit)))
+                    .collect();
+            }
+        }
+
+        self.transfers = std::mem::take(&mut self.transfers)
+            .into_iter()
+            .filter_map(|((from, to), transfer)| Some(((new_id(&from)?, new_id(&to)?), transfer)))
+            .collect::<BTreeMap<_, _>>();
+
+        // A rule left with no stops can't match anything, which is right
+        let remap_stops = |stops: &mut Option<BTreeSet<StopID>>| {
+            if let Some(stops) = stops {
+                *stops = stops.iter().filter_map(new_id).collect();
+            }
+        };
+        for rule in &mut self.fares {
+            remap_stops(&mut rule.from_stops);
+            remap_stops(&mut rule.to_stops);
+        }
+
+        self.build_timetable();
+    }
+}
@@ -1,72 +1,181 @@
-use std::collections::BTreeMap;
-use std::fs::File;
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
 
 use anyhow::Result;
-use chrono::NaiveTime;
-use geo::{Contains, Point};
+use chrono::NaiveDate;
+use geo::Point;
 use serde::Deserialize;
-use utils::Mercator;
 
+use super::files::GtfsFiles;
 use super::ids::{IDMapping, orig_ids};
-use super::{GtfsModel, NextStep, Route, RouteID, Stop, StopID, Trip, TripID};
-use crate::RoadID;
-
-// Move to mod after deciding to store every day
-#[derive(Clone, Copy, Debug, PartialEq)]
-enum Day {
-    Monday = 0,
-    Tuesday = 1,
-    Wednesday = 2,
-    Thursday = 3,
-    Friday = 4,
-    Saturday = 5,
-    Sunday = 6,
-}
+use super::shapes::ShapePoint;
+use super::stations::{Pathways, StationPart, WALKING_SPEED, build_stations, straight_line_time};
+use super::validate::GtfsIssue;
+use super::{
+    Agency, FareRule, GtfsModel, Route, RouteID, Service, ServiceID, ServiceTime, Shape, ShapeID,
+    Stop, StopID, StopTime, StudyArea, Transfer, Trip,
+};
+use crate::Timer;
 
 impl GtfsModel {
-    /// Takes a path to a GTFS directory. If no Mercator is specified, keeps WGS84 coordinates and
-    /// also doesn't calculate `next_steps`.
-    pub fn parse(dir_path: &str, mercator: Option<&Mercator>) -> Result<GtfsModel> {
-        info!("Scraping trips.txt");
-        let mut trip_to_service: BTreeMap<orig_ids::TripID, orig_ids::ServiceID> = BTreeMap::new();
-        let mut trip_to_route: BTreeMap<orig_ids::TripID, orig_ids::RouteID> = BTreeMap::new();
-        for rec in
-            csv::Reader::from_reader(File::open(format!("{dir_path}/trips.txt"))?).deserialize()
-        {
-            let rec: TripRow = rec?;
-            trip_to_service.insert(rec.trip_id.clone(), rec.service_id);
-            trip_to_route.insert(rec.trip_id, rec.route_id);
-        }
-
-        info!("Scraping calendar.txt");
-        let mut service_to_days: BTreeMap<orig_ids::ServiceID, Vec<Day>> = BTreeMap::new();
-        for rec in
-            csv::Reader::from_reader(File::open(format!("{dir_path}/calendar.txt"))?).deserialize()
-        {
-            let rec: CalendarRow = rec?;
-            let mut days = Vec::new();
-            for (day, include) in [
-                (Day::Monday, rec.monday),
-                (Day::Tuesday, rec.tuesday),
-                (Day::Wednesday, rec.wednesday),
-                (Day::Thursday, rec.thursday),
-                (Day::Friday, rec.friday),
-                (Day::Saturday, rec.saturday),
-                (Day::Sunday, rec.sunday),
-            ] {
-                if include == 1 {
-                    days.push(day);
-                }
-            }
-            service_to_days.insert(rec.service_id, days);
-        }
-
-        info!("Scraping routes.txt");
+    /// Takes a path to a GTFS directory or zip file. Stops outside the study area are skipped,
+    /// and trips leaving and re-entering it are split. If no area is specified, keeps everything
+    /// in WGS84 coordinates and also doesn't build the timetable.
+    ///
+    /// `stop_times.txt` is read one trip at a time, so very large feeds don't need much memory
+    /// beyond what's kept. Only trips with some stop in the study area are read from `trips.txt`.
+    pub fn parse(path: &str, area: Option<&StudyArea>, timer: &mut Timer) -> Result<GtfsModel> {
+        let (model, issues) = Self::parse_with_issues(path, area, timer)?;
+        summarize_issues(&issues);
+        Ok(model)
+    }
+
+    /// Like `parse`, but takes the bytes of a zipped GTFS feed.
+    pub fn parse_bytes(
+        bytes: Vec<u8>,
+        area: Option<&StudyArea>,
+        timer: &mut Timer,
+    ) -> Result<GtfsModel> {
+        let mut issues = Vec::new();
+        let dates = area.and_then(|a| a.dates);
+        let model = Self::parse_files(
+            GtfsFiles::from_bytes(bytes)?,
+            area,
+            dates,
+            &mut issues,
+            timer,
+        )?;
+        summarize_issues(&issues);
+        Ok(model)
+    }
+
+    /// Like `parse` without a study area, but only keeps trips running on some day in the
+    /// inclusive range `dates`
+    pub fn parse_between(
+        path: &str,
+        dates: (NaiveDate, NaiveDate),
+        timer: &mut Timer,
+    ) -> Result<GtfsModel> {
+        let mut issues = Vec::new();
+        let model = Self::parse_files(
+            GtfsFiles::open(path)?,
+            None,
+            Some(dates),
+            &mut issues,
+            timer,
+        )?;
+        summarize_issues(&issues);
+        Ok(model)
+    }
+
+    /// Like `parse`, but also returns all problems found with the input.
+    pub fn parse_with_issues(
+        path: &str,
+        area: Option<&StudyArea>,
+        timer: &mut Timer,
+    ) -> Result<(GtfsModel, Vec<GtfsIssue>)> {
+        let mut issues = Vec::new();
+        let dates = area.and_then(|a| a.dates);
+        let model = Self::parse_files(GtfsFiles::open(path)?, area, dates, &mut issues, timer)?;
+        Ok((model, issues))
+    }
+
+    fn parse_files(
+        mut files: GtfsFiles,
+        area: Option<&StudyArea>,
+        dates: Option<(NaiveDate, NaiveDate)>,
+        issues: &mut Vec<GtfsIssue>,
+        timer: &mut Timer,
+    ) -> Result<GtfsModel> {
+        let mercator = area.map(|a| &a.mercator);
+        timer.push("parse GTFS");
+
+        let mut services_table: BTreeMap<orig_ids::ServiceID, Service> = BTreeMap::new();
+        // Feeds need at least one of calendar.txt and calendar_dates.txt, but not both
+        if files.exists("calendar.txt")? {
+            timer.step("scrape calendar.txt");
+            for rec in files.csv("calendar.txt")?.deserialize() {
+                let rec: CalendarRow = rec?;
+                let mut service = Service::new(rec.service_id.clone());
+                service.weekdays = [
+                    rec.monday == 1,
+                    rec.tuesday == 1,
+                    rec.wednesday == 1,
+                    rec.thursday == 1,
+                    rec.friday == 1,
+                    rec.saturday == 1,
+                    rec.sunday == 1,
+                ];
+                service.date_range =
+                    Some((parse_date(&rec.start_date)?, parse_date(&rec.end_date)?));
+                services_table.insert(rec.service_id, service);
+            }
+        }
+
+        if files.exists("calendar_dates.txt")? {
+            timer.step("scrape calendar_dates.txt");
+            for rec in files.csv("calendar_dates.txt")?.deserialize() {
+                let rec: CalendarDateRow = rec?;
+                let date = parse_date(&rec.date)?;
+                let service = services_table
+                    .entry(rec.service_id.clone())
+                    .or_insert_with(|| Service::new(rec.service_id));
+                match rec.exception_type {
+                    1 => {
+                        service.added_dates.insert(date);
+                    }
+                    2 => {
+                        service.removed_dates.insert(date);
+                    }
+                    x => warn!("Unknown exception_type {x} in calendar_dates.txt"),
+                }
+            }
+        }
+
+        // Skip everything for services not running when needed
+        let mut unused_services = BTreeSet::new();
+        if let Some((start, end)) = dates {
+            services_table.retain(|id, service| {
+                let used = service.runs_between(start, end);
+                if !used {
+                    unused_services.insert(id.clone());
+                }
+                used
+            });
+        }
+
+        timer.step("scrape agency.txt");
+        let mut agencies: BTreeMap<orig_ids::AgencyID, Agency> = BTreeMap::new();
+        if files.exists("agency.txt")? {
+            for rec in files.csv("agency.txt")?.deserialize() {
+                let rec: AgencyRow = rec?;
+                let orig_id = rec
+                    .agency_id
+                    .unwrap_or_else(|| orig_ids::AgencyID::new(&rec.agency_name));
+                agencies.insert(
+                    orig_id.clone(),
+                    Agency {
+                        orig_id,
+                        name: rec.agency_name,
+                        url: rec.agency_url,
+                        timezone: rec.agency_timezone,
+                    },
+                );
+            }
+        }
+
+        timer.step("scrape routes.txt");
         let mut routes_table: BTreeMap<orig_ids::RouteID, Route> = BTreeMap::new();
-        for rec in
-            csv::Reader::from_reader(File::open(format!("{dir_path}/routes.txt"))?).deserialize()
-        {
+        // Fares v2 may refer to routes by network
+        let mut networks: BTreeMap<String, Vec<orig_ids::RouteID>> = BTreeMap::new();
+        for rec in files.csv("routes.txt")?.deserialize() {
             let rec: RouteRow = rec?;
+            if let Some(network_id) = rec.network_id {
+                networks
+                    .entry(network_id)
+                    .or_default()
+                    .push(rec.route_id.clone());
+            }
             routes_table.insert(
                 rec.route_id.clone(),
                 Route {
@@ -74,29 +183,76 @@ impl GtfsModel {
                     short_name: rec.route_short_name,
                     long_name: rec.route_long_name,
                     description: rec.route_desc,
+                    route_type: rec.route_type,
+                    // agency_id may be left out when there's only one
+                    agency: match rec.agency_id {
+                        Some(id) => agencies.get(&id).cloned(),
+                        None if agencies.len() == 1 => agencies.values().next().cloned(),
+                        None => None,
+                    },
                 },
             );
         }
 
-        info!("Scraping stops.txt");
+        timer.step("scrape stops.txt");
         let mut stop_ids: IDMapping<orig_ids::StopID, StopID> = IDMapping::new();
         let mut stops: Vec<Stop> = Vec::new();
-        for rec in
-            csv::Reader::from_reader(File::open(format!("{dir_path}/stops.txt"))?).deserialize()
-        {
+        // Other files may refer to these, but it's not a problem
+        let mut out_of_bounds_stops: BTreeSet<orig_ids::StopID> = BTreeSet::new();
+        // Fares v1 may refer to stops by zone
+        let mut zones: BTreeMap<String, BTreeSet<StopID>> = BTreeMap::new();
+        // Everything in stops.txt, to build stations and pathways
+        let mut station_parts: BTreeMap<orig_ids::StopID, StationPart> = BTreeMap::new();
+        let mut platform_parents: BTreeMap<StopID, orig_ids::StopID> = BTreeMap::new();
+        for rec in files.csv("stops.txt")?.deserialize() {
             let rec: StopRow = rec?;
+            let location_type = rec.location_type.unwrap_or(0);
+            let point = match (rec.stop_lon, rec.stop_lat) {
+                (Some(lon), Some(lat)) => Some(Point::new(lon, lat)),
+                _ => None,
+            };
+            station_parts.insert(
+                rec.stop_id.clone(),
+                StationPart {
+                    name: rec.stop_name.clone(),
+                    location_type,
+                    parent: rec.parent_station.clone(),
+                    point,
+                    wheelchair_boarding: parse_wheelchair(rec.wheelchair_boarding),
+                },
+            );
+            // Only stops and platforms are used by stop_times.txt
+            if location_type != 0 {
+                continue;
+            }
+            let Some(point) = point else {
+                warn!("Skipping stop {:?} without a location", rec.stop_id);
+                continue;
+            };
 
-            // TODO Move code to utils
-            let point = Point::new(rec.stop_lon, rec.stop_lat);
-            if mercator
-                .as_ref()
-                .map(|m| !m.wgs84_bounds.contains(&point))
-                .unwrap_or(false)
-            {
+            if area.is_some_and(|area| !area.contains(point)) {
+                issues.push(GtfsIssue::StopOutOfBounds {
+                    stop_id: rec.stop_id.clone(),
+                    point,
+                });
+                out_of_bounds_stops.insert(rec.stop_id);
                 continue;
             }
 
-            stop_ids.insert_new(rec.stop_id.clone())?;
+            if stop_ids.get(&rec.stop_id).is_some() {
+                issues.push(GtfsIssue::DuplicateStop {
+                    stop_id: rec.stop_id,
+                    point,
+                });
+                continue;
+            }
+            let stop_id = stop_ids.insert_new(rec.stop_id.clone())?;
+            if let Some(zone_id) = rec.zone_id {
+                zones.entry(zone_id).or_default().insert(stop_id);
+            }
+            if let Some(parent) = rec.parent_station {
+                platform_parents.insert(stop_id, parent);
+            }
             stops.push(Stop {
                 name: rec.stop_name,
                 orig_id: rec.stop_id,
@@ -104,53 +260,382 @@ impl GtfsModel {
                     Some(mercator) => mercator.to_mercator(&point),
                     None => point,
                 },
-                next_steps: Vec::new(),
-                // Dummy value, fill out later
-                road: RoadID(0),
+                station: None,
+                // Stops in a station may inherit this later
+                wheelchair_boarding: parse_wheelchair(rec.wheelchair_boarding),
+                // Fill out later, when snapping
+                access: Vec::new(),
+                departures: Vec::new(),
             });
         }
 
-        info!("Scraping stop_times.txt");
-        let mut trips_table: BTreeMap<orig_ids::TripID, Trip> = BTreeMap::new();
-        let mut route_ids: IDMapping<orig_ids::RouteID, RouteID> = IDMapping::new();
-        for rec in csv::Reader::from_reader(File::open(format!("{dir_path}/stop_times.txt"))?)
-            .deserialize()
-        {
+        let mut pathways = Pathways::default();
+        if files.exists("pathways.txt")? {
+            timer.step("scrape pathways.txt");
+            for rec in files.csv("pathways.txt")?.deserialize() {
+                let rec: PathwayRow = rec?;
+                let mut endpoints = Vec::new();
+                for stop_id in [&rec.from_stop_id, &rec.to_stop_id] {
+                    match station_parts.get(stop_id) {
+                        Some(part) => endpoints.push(part.point),
+                        None => issues.push(GtfsIssue::UnknownStop {
+                            file: "pathways.txt",
+                            stop_id: stop_id.clone(),
+                        }),
+                    }
+                }
+                let &[pt1, pt2] = endpoints.as_slice() else {
+                    continue;
+                };
+                let time = match (rec.traversal_time, rec.length, pt1, pt2) {
+                    (Some(seconds), _, _, _) => Duration::from_secs(seconds),
+                    (None, Some(length), _, _) => Duration::from_secs_f64(length / WALKING_SPEED),
+                    (None, None, Some(pt1), Some(pt2)) => straight_line_time(pt1, pt2),
+                    // Nodes inside a station might not have a location
+                    (None, None, _, _) => Duration::ZERO,
+                };
+                pathways.add(
+                    rec.from_stop_id,
+                    rec.to_stop_id,
+                    time,
+                    rec.is_bidirectional == 1,
+                );
+            }
+        }
+        let stations = build_stations(
+            &mut stops,
+            platform_parents,
+            &station_parts,
+            &pathways,
+            area,
+        );
+
+        let mut transfers = BTreeMap::new();
+        if files.exists("transfers.txt")? {
+            timer.step("scrape transfers.txt");
+            let mut skipped = 0;
+            for rec in files.csv("transfers.txt")?.deserialize() {
+                let rec: TransferRow = rec?;
+                // TODO Handle rules specific to routes or trips
+                if rec.from_route_id.is_some()
+                    || rec.to_route_id.is_some()
+                    || rec.from_trip_id.is_some()
+                    || rec.to_trip_id.is_some()
+                {
+                    skipped += 1;
+                    continue;
+                }
+                let (Some(from_stop_id), Some(to_stop_id)) = (rec.from_stop_id, rec.to_stop_id)
+                else {
+                    skipped += 1;
+                    continue;
+                };
+                let mut endpoints = Vec::new();
+                for stop_id in [from_stop_id, to_stop_id] {
+                    if let Some(stop) = stop_ids.get(&stop_id) {
+                        endpoints.push(stop);
+                    } else if !out_of_bounds_stops.contains(&stop_id) {
+                        issues.push(GtfsIssue::UnknownStop {
+                            file: "transfers.txt",
+                            stop_id,
+                        });
+                    }
+                }
+                // Skip out-of-bounds stops
+                let &[from, to] = endpoints.as_slice() else {
+                    continue;
+                };
+                let transfer = match (rec.transfer_type.unwrap_or(0), rec.min_transfer_time) {
+                    // A recommended transfer point doesn't change any timing
+                    (0, _) => continue,
+                    (1, _) => Transfer::Timed,
+                    (2, Some(seconds)) => Transfer::MinTime(Duration::from_secs(seconds)),
+                    (2, None) => {
+                        warn!("Ignoring transfers.txt entry missing min_transfer_time");
+                        continue;
+                    }
+                    (3, _) => Transfer::Forbidden,
+                    (x, _) => {
+                        warn!("Ignoring transfers.txt entry with transfer_type {x}");
+                        continue;
+                    }
+                };
+                transfers.insert((from, to), transfer);
+            }
+            if skipped > 0 {
+                warn!("Ignored {skipped} transfers.txt entries for specific routes or trips");
+            }
+        }
+
+        timer.step("scrape stop_times.txt");
+        // Only one trip is built at a time, so memory doesn't grow with the size of the whole file.
+        // Almost every feed keeps the rows of each trip together and in order. Any trips that
+        // don't are read again at the end.
+        let mut pieces: Vec<TripPiece> = Vec::new();
+        let mut current: Option<CurrentTrip> = None;
+        let mut finished_trips: BTreeSet<orig_ids::TripID> = BTreeSet::new();
+        let mut unsorted_trips: BTreeSet<orig_ids::TripID> = BTreeSet::new();
+        let mut unknown_stops: BTreeSet<orig_ids::StopID> = BTreeSet::new();
+        let mut rows = 0;
+        for rec in files.csv("stop_times.txt")?.deserialize() {
             let rec: StopTimeRow = rec?;
-            let Ok(arrival_time) = NaiveTime::parse_from_str(&rec.arrival_time, "%H:%M:%S") else {
-                // TODO Handle times > 24 hours
+            rows += 1;
+            if rows % PROGRESS_ROWS == 0 {
+                timer.progress(rows, "rows");
+            }
+            if unsorted_trips.contains(&rec.trip_id) {
                 continue;
-            };
+            }
 
-            // Skip out-of-bounds stops
-            let Some(stop_id) = stop_ids.get(&rec.stop_id) else {
+            if current
+                .as_ref()
+                .is_none_or(|trip| trip.orig_id != rec.trip_id)
+            {
+                if let Some(trip) = current.take() {
+                    finished_trips.insert(trip.orig_id.clone());
+                    pieces.extend(trip.pieces);
+                }
+                if finished_trips.contains(&rec.trip_id) {
+                    unsorted_trips.insert(rec.trip_id);
+                    continue;
+                }
+                current = Some(CurrentTrip::new(rec.trip_id.clone()));
+            }
+            let trip = current.as_mut().unwrap();
+            if trip
+                .last_sequence
+                .is_some_and(|seq| rec.stop_sequence < seq)
+            {
+                unsorted_trips.insert(rec.trip_id);
+                current = None;
                 continue;
-            };
+            }
+            trip.add_row(
+                rec,
+                &stop_ids,
+                &out_of_bounds_stops,
+                &mut unknown_stops,
+                issues,
+            );
+        }
+        if let Some(trip) = current.take() {
+            pieces.extend(trip.pieces);
+        }
+        timer.progress(rows, "rows");
+        drop(finished_trips);
 
-            // Which days does this stop occur on?
-            let service = &trip_to_service[&rec.trip_id];
-            let Some(days) = service_to_days.get(service) else {
-                warn!("Don't know what days service {service:?} is on");
-                continue;
-            };
+        if !unsorted_trips.is_empty() {
+            warn!(
+                "stop_times.txt has rows for {} trips in different places or out of order. Reading them again.",
+                unsorted_trips.len()
+            );
+            timer.step("scrape unsorted trips from stop_times.txt");
+            let mut unsorted_rows: BTreeMap<orig_ids::TripID, Vec<StopTimeRow>> = BTreeMap::new();
+            for rec in files.csv("stop_times.txt")?.deserialize() {
+                let rec: StopTimeRow = rec?;
+                if unsorted_trips.contains(&rec.trip_id) {
+                    unsorted_rows
+                        .entry(rec.trip_id.clone())
+                        .or_default()
+                        .push(rec);
+                }
+            }
+            // Forget anything from the first time through
+            pieces.retain(|piece| !unsorted_trips.contains(&piece.orig_id));
+            issues.retain(|issue| match issue {
+                GtfsIssue::TimesNotIncreasing { trip_id, .. } => !unsorted_trips.contains(trip_id),
+                _ => true,
+            });
+            for (trip_id, mut rows) in unsorted_rows {
+                rows.sort_by_key(|rec| rec.stop_sequence);
+                let mut trip = CurrentTrip::new(trip_id);
+                for rec in rows {
+                    trip.add_row(
+                        rec,
+                        &stop_ids,
+                        &out_of_bounds_stops,
+                        &mut unknown_stops,
+                        issues,
+                    );
+                }
+                pieces.extend(trip.pieces);
+            }
+        }
+        // Keep the same order no matter how stop_times.txt is sorted
+        pieces.sort_by(|a, b| (&a.orig_id, a.piece).cmp(&(&b.orig_id, b.piece)));
+        // Only these trips are needed from trips.txt and frequencies.txt
+        let trips_in_area: BTreeSet<orig_ids::TripID> = pieces
+            .iter()
+            .filter(|piece| piece.piece == 0)
+            .map(|piece| piece.orig_id.clone())
+            .collect();
 
-            // TODO For now, only keep Monday
-            if !days.contains(&Day::Monday) {
+        timer.step("scrape trips.txt");
+        let mut trips_info: BTreeMap<orig_ids::TripID, TripInfo> = BTreeMap::new();
+        // Trips with problems or not running when needed. Other files may refer to these, but
+        // it's not a problem.
+        let mut skipped_trips: BTreeSet<orig_ids::TripID> = BTreeSet::new();
+        let mut unused_trips = 0;
+        for rec in files.csv("trips.txt")?.deserialize() {
+            let rec: TripRow = rec?;
+            // Don't keep anything about trips only outside the study area
+            if !trips_in_area.contains(&rec.trip_id) {
+                continue;
+            }
+            if unused_services.contains(&rec.service_id) {
+                unused_trips += 1;
+                skipped_trips.insert(rec.trip_id);
+                continue;
+            }
+            if !routes_table.contains_key(&rec.route_id) {
+                issues.push(GtfsIssue::UnknownRoute {
+                    trip_id: rec.trip_id.clone(),
+                    route_id: rec.route_id,
+                });
+                skipped_trips.insert(rec.trip_id);
                 continue;
             }
+            // Without this, we don't know what days the trip runs on
+            if !services_table.contains_key(&rec.service_id) {
+                issues.push(GtfsIssue::UnknownService {
+                    trip_id: rec.trip_id.clone(),
+                    service_id: rec.service_id,
+                });
+                skipped_trips.insert(rec.trip_id);
+                continue;
+            }
+            trips_info.insert(
+                rec.trip_id,
+                TripInfo {
+                    route: rec.route_id,
+                    service: rec.service_id,
+                    shape: rec.shape_id,
+                    wheelchair: parse_wheelchair(rec.wheelchair_accessible),
+                },
+            );
+        }
+        if unused_trips > 0 {
+            info!("Skipped {unused_trips} trips not running on the requested dates");
+        }
+        for trip_id in &trips_in_area {
+            if !trips_info.contains_key(trip_id) && !skipped_trips.contains(trip_id) {
+                issues.push(GtfsIssue::UnknownTrip {
+                    file: "stop_times.txt",
+                    trip_id: trip_id.clone(),
+                });
+            }
+        }
 
-            trips_table
-                .entry(rec.trip_id.clone())
-                .or_insert_with(|| Trip {
-                    stop_sequence: Vec::new(),
-                    route: route_ids.insert_idempotent(&trip_to_route[&rec.trip_id]),
-                })
-                .stop_sequence
-                .push((stop_id, arrival_time));
+        let mut frequencies: BTreeMap<orig_ids::TripID, Vec<FrequencyRow>> = BTreeMap::new();
+        if files.exists("frequencies.txt")? {
+            timer.step("scrape frequencies.txt");
+            for rec in files.csv("frequencies.txt")?.deserialize() {
+                let rec: FrequencyRow = rec?;
+                if !trips_in_area.contains(&rec.trip_id) || skipped_trips.contains(&rec.trip_id) {
+                    continue;
+                }
+                if !trips_info.contains_key(&rec.trip_id) {
+                    issues.push(GtfsIssue::UnknownTrip {
+                        file: "frequencies.txt",
+                        trip_id: rec.trip_id,
+                    });
+                    continue;
+                }
+                frequencies
+                    .entry(rec.trip_id.clone())
+                    .or_default()
+                    .push(rec);
+            }
         }
 
-        // Produce compact vectors of used things
-        let trips: Vec<Trip> = trips_table.into_values().collect();
+        // Only keep shapes used by trips in bounds
+        let mut shape_points: BTreeMap<orig_ids::ShapeID, Vec<(usize, ShapePoint)>> =
+            BTreeMap::new();
+        for info in trips_info.values() {
+            if let Some(ref shape_id) = info.shape {
+                shape_points.insert(shape_id.clone(), Vec::new());
+            }
+        }
+        if !shape_points.is_empty() && files.exists("shapes.txt")? {
+            timer.step("scrape shapes.txt");
+            for rec in files.csv("shapes.txt")?.deserialize() {
+                let rec: ShapeRow = rec?;
+                if let Some(points) = shape_points.get_mut(&rec.shape_id) {
+                    let point = Point::new(rec.shape_pt_lon, rec.shape_pt_lat);
+                    points.push((
+                        rec.shape_pt_sequence,
+                        ShapePoint {
+                            point: match mercator {
+                                Some(mercator) => mercator.to_mercator(&point),
+                                None => point,
+                            },
+                            dist_traveled: rec.shape_dist_traveled,
+                        },
+                    ));
+                }
+            }
+        }
+        let shape_points: BTreeMap<orig_ids::ShapeID, Vec<ShapePoint>> = shape_points
+            .into_iter()
+            .map(|(shape_id, mut points)| {
+                points.sort_by_key(|(seq, _)| *seq);
+                (shape_id, points.into_iter().map(|(_, pt)| pt).collect())
+            })
+            .collect();
+
+        // Produce compact vectors of used things. Trips with the same shape might visit
+        // different stops, so shapes are per stop sequence.
+        let mut route_ids: IDMapping<orig_ids::RouteID, RouteID> = IDMapping::new();
+        let mut service_ids: IDMapping<orig_ids::ServiceID, ServiceID> = IDMapping::new();
+        let mut trips: Vec<Trip> = Vec::new();
+        let mut shapes: Vec<Shape> = Vec::new();
+        let mut shape_ids: BTreeMap<(orig_ids::ShapeID, Vec<StopID>), ShapeID> = BTreeMap::new();
+        for piece in pieces {
+            let Some(info) = trips_info.get(&piece.orig_id) else {
+                continue;
+            };
+            let mut trip = Trip {
+                orig_id: piece.orig_id,
+                start_time: piece.start_time,
+                stop_sequence: piece.stop_sequence,
+                route: route_ids.insert_idempotent(&info.route),
+                service: service_ids.insert_idempotent(&info.service),
+                shape: None,
+                wheelchair_accessible: info.wheelchair,
+            };
+
+            if let Some(ref shape_id) = info.shape {
+                let points = &shape_points[shape_id];
+                if points.len() < 2 {
+                    issues.push(GtfsIssue::UnknownShape {
+                        trip_id: trip.orig_id.clone(),
+                        shape_id: shape_id.clone(),
+                    });
+                } else {
+                    let key = (
+                        shape_id.clone(),
+                        trip.stop_sequence.iter().map(|st| st.stop).collect(),
+                    );
+                    trip.shape = Some(*shape_ids.entry(key).or_insert_with(|| {
+                        let stop_pts: Vec<(Point, Option<f64>)> = trip
+                            .stop_sequence
+                            .iter()
+                            .zip(&piece.shape_dists)
+                            .map(|(st, dist)| (stops[st.stop.0].point, *dist))
+                            .collect();
+                        shapes.push(Shape::new(points, &stop_pts));
+                        ShapeID(shapes.len() - 1)
+                    }));
+                }
+            }
+
+            match frequencies.get(&trip.orig_id) {
+                Some(rows) => {
+                    expand_frequencies(trip, piece.start_time, rows, &mut trips, issues);
+                }
+                None => trips.push(trip),
+            }
+        }
 
         let mut routes: Vec<Route> = route_ids
             .borrow()
@@ -160,36 +645,34 @@ impl GtfsModel {
         // TODO Sorting is a bit silly; we could fill this out directly in order
         routes.sort_by_key(|r| route_ids.get(&r.orig_id));
 
+        let mut services: Vec<Service> = service_ids
+            .borrow()
+            .keys()
+            .map(|orig_id| services_table.remove(orig_id).unwrap())
+            .collect();
+        services.sort_by_key(|s| service_ids.get(&s.orig_id));
+
+        timer.step("scrape fares");
+        let fares = scrape_fares(&mut files, &stop_ids, &route_ids, zones, networks, issues)?;
+
         let mut model = GtfsModel {
             stops,
             trips,
             routes,
+            services,
+            shapes,
+            stations,
+            transfers,
+            fares,
+            patterns: Vec::new(),
         };
         if mercator.is_some() {
-            model.precompute_next_steps();
+            timer.step("build timetable");
+            model.build_timetable();
         }
+        timer.pop();
         Ok(model)
     }
-
-    pub(crate) fn precompute_next_steps(&mut self) {
-        for (idx, trip) in self.trips.iter().enumerate() {
-            let trip_id = TripID(idx);
-            for pair in trip.stop_sequence.windows(2) {
-                let (stop1, time1) = &pair[0];
-                let (stop2, time2) = &pair[1];
-                self.stops[stop1.0].next_steps.push(NextStep {
-                    time1: *time1,
-                    trip: trip_id,
-                    stop2: *stop2,
-                    time2: *time2,
-                });
-            }
-        }
-
-        for stop in &mut self.stops {
-            stop.next_steps.sort_by_key(|x| x.time1);
-        }
-    }
 }
 
 #[derive(Deserialize)]
@@ -197,6 +680,8 @@ struct TripRow {
     trip_id: orig_ids::TripID,
     service_id: orig_ids::ServiceID,
     route_id: orig_ids::RouteID,
+    shape_id: Option<orig_ids::ShapeID>,
+    wheelchair_accessible: Option<u8>,
 }
 
 #[derive(Deserialize)]
@@ -209,14 +694,37 @@ struct CalendarRow {
     friday: usize,
     saturday: usize,
     sunday: usize,
+    start_date: String,
+    end_date: String,
+}
+
+#[derive(Deserialize)]
+struct CalendarDateRow {
+    service_id: orig_ids::ServiceID,
+    date: String,
+    exception_type: usize,
 }
 
 #[derive(Deserialize)]
 struct StopRow {
     stop_id: orig_ids::StopID,
     stop_name: String,
-    stop_lon: f64,
-    stop_lat: f64,
+    // Only required for stops, stations, and entrances
+    stop_lon: Option<f64>,
+    stop_lat: Option<f64>,
+    zone_id: Option<String>,
+    location_type: Option<u8>,
+    parent_station: Option<orig_ids::StopID>,
+    wheelchair_boarding: Option<u8>,
+}
+
+#[derive(Deserialize)]
+struct PathwayRow {
+    from_stop_id: orig_ids::StopID,
+    to_stop_id: orig_ids::StopID,
+    is_bidirectional: u8,
+    length: Option<f64>,
+    traversal_time: Option<u64>,
 }
 
 #[derive(Deserialize)]
@@ -224,12 +732,573 @@ struct StopTimeRow {
     trip_id: orig_ids::TripID,
     stop_id: orig_ids::StopID,
     arrival_time: String,
+    departure_time: String,
+    stop_sequence: u32,
+    shape_dist_traveled: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct ShapeRow {
+    shape_id: orig_ids::ShapeID,
+    shape_pt_lat: f64,
+    shape_pt_lon: f64,
+    shape_pt_sequence: usize,
+    shape_dist_traveled: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct TransferRow {
+    from_stop_id: Option<orig_ids::StopID>,
+    to_stop_id: Option<orig_ids::StopID>,
+    from_route_id: Option<orig_ids::RouteID>,
+    to_route_id: Option<orig_ids::RouteID>,
+    from_trip_id: Option<orig_ids::TripID>,
+    to_trip_id: Option<orig_ids::TripID>,
+    transfer_type: Option<usize>,
+    min_transfer_time: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct FrequencyRow {
+    trip_id: orig_ids::TripID,
+    start_time: String,
+    end_time: String,
+    headway_secs: u32,
+    // exact_times doesn't matter; both cases expand into trips the same way
+}
+
+#[derive(Deserialize)]
+struct AgencyRow {
+    agency_id: Option<orig_ids::AgencyID>,
+    agency_name: String,
+    agency_url: String,
+    agency_timezone: String,
 }
 
 #[derive(Deserialize)]
 struct RouteRow {
     route_id: orig_ids::RouteID,
+    agency_id: Option<orig_ids::AgencyID>,
     route_short_name: Option<String>,
     route_long_name: Option<String>,
     route_desc: Option<String>,
+    route_type: u16,
+    network_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FareAttributeRow {
+    fare_id: String,
+    price: f64,
+    currency_type: String,
+}
+
+#[derive(Deserialize)]
+struct FareRuleRow {
+    fare_id: String,
+    route_id: Option<orig_ids::RouteID>,
+    origin_id: Option<String>,
+    destination_id: Option<String>,
+    contains_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FareProductRow {
+    fare_product_id: String,
+    amount: f64,
+    currency: String,
+}
+
+#[derive(Deserialize)]
+struct FareLegRuleRow {
+    network_id: Option<String>,
+    from_area_id: Option<String>,
+    to_area_id: Option<String>,
+    fare_product_id: String,
+    rule_priority: Option<i32>,
+}
+
+#[derive(Deserialize)]
+struct StopAreaRow {
+    area_id: String,
+    stop_id: orig_ids::StopID,
+}
+
+#[derive(Deserialize)]
+struct RouteNetworkRow {
+    network_id: String,
+    route_id: orig_ids::RouteID,
+}
+
+/// Report progress through stop_times.txt this often
+const PROGRESS_ROWS: usize = 1_000_000;
+
+/// What's needed from trips.txt to build a trip
+struct TripInfo {
+    route: orig_ids::RouteID,
+    service: orig_ids::ServiceID,
+    shape: Option<orig_ids::ShapeID>,
+    wheelchair: Option<bool>,
+}
+
+/// The trip currently being read from stop_times.txt
+struct CurrentTrip {
+    orig_id: orig_ids::TripID,
+    /// The time at the very first stop, even if that stop is out-of-bounds
+    start_time: Option<ServiceTime>,
+    /// The departure from the previous stop, even if that stop is out-of-bounds
+    last_departure: Option<ServiceTime>,
+    last_sequence: Option<u32>,
+    pieces: Vec<TripPiece>,
+    /// Has the trip left the study area since the last piece?
+    gap: bool,
+}
+
+impl CurrentTrip {
+    fn new(orig_id: orig_ids::TripID) -> Self {
+        Self {
+            orig_id,
+            start_time: None,
+            last_departure: None,
+            last_sequence: None,
+            pieces: Vec::new(),
+            gap: false,
+        }
+    }
+
+    /// Handles the next row of stop_times.txt for this trip
+    fn add_row(
+        &mut self,
+        rec: StopTimeRow,
+        stop_ids: &IDMapping<orig_ids::StopID, StopID>,
+        out_of_bounds_stops: &BTreeSet<orig_ids::StopID>,
+        unknown_stops: &mut BTreeSet<orig_ids::StopID>,
+        issues: &mut Vec<GtfsIssue>,
+    ) {
+        self.last_sequence = Some(rec.stop_sequence);
+
+        // If only one time is specified, the vehicle doesn't wait at the stop
+        let parse = |x: &str| {
+            (!x.trim().is_empty())
+                .then(|| ServiceTime::parse(x))
+                .transpose()
+        };
+        let (arrival, departure) = match (parse(&rec.arrival_time), parse(&rec.departure_time)) {
+            (Ok(Some(arrival)), Ok(Some(departure))) => {
+                if departure < arrival {
+                    issues.push(GtfsIssue::TimesNotIncreasing {
+                        trip_id: rec.trip_id,
+                        stop_id: rec.stop_id,
+                    });
+                    return;
+                }
+                (arrival, departure)
+            }
+            (Ok(Some(time)), Ok(None)) | (Ok(None), Ok(Some(time))) => (time, time),
+            (Ok(None), Ok(None)) => {
+                // TODO Interpolate times for stops that aren't timepoints
+                return;
+            }
+            (Err(_), _) | (_, Err(_)) => {
+                issues.push(GtfsIssue::BadStopTime {
+                    trip_id: rec.trip_id,
+                    stop_id: rec.stop_id,
+                });
+                return;
+            }
+        };
+        // Check against every earlier stop, so going backwards across a gap is caught too
+        if self.last_departure.is_some_and(|prev| arrival < prev) {
+            issues.push(GtfsIssue::TimesNotIncreasing {
+                trip_id: rec.trip_id,
+                stop_id: rec.stop_id,
+            });
+            return;
+        }
+        self.last_departure = Some(departure);
+        let start_time = *self.start_time.get_or_insert(arrival);
+
+        // Skip out-of-bounds stops. A trip leaving the study area and coming back is split
+        // into pieces, so that it doesn't look like the vehicle jumps straight between the
+        // stops on either side of the gap.
+        let Some(stop_id) = stop_ids.get(&rec.stop_id) else {
+            if out_of_bounds_stops.contains(&rec.stop_id) {
+                self.gap = true;
+            } else if unknown_stops.insert(rec.stop_id.clone()) {
+                issues.push(GtfsIssue::UnknownStop {
+                    file: "stop_times.txt",
+                    stop_id: rec.stop_id,
+                });
+            }
+            return;
+        };
+
+        if self.gap || self.pieces.is_empty() {
+            self.gap = false;
+            self.pieces.push(TripPiece {
+                orig_id: rec.trip_id.clone(),
+                piece: self.pieces.len(),
+                start_time,
+                stop_sequence: Vec::new(),
+                shape_dists: Vec::new(),
+            });
+        }
+        let piece = self.pieces.last_mut().unwrap();
+        piece.stop_sequence.push(StopTime {
+            stop: stop_id,
+            arrival,
+            departure,
+        });
+        piece.shape_dists.push(rec.shape_dist_traveled);
+    }
+}
+
+/// Part of a trip inside the study area
+struct TripPiece {
+    orig_id: orig_ids::TripID,
+    piece: usize,
+    /// The time at the very first stop of the whole trip, even if that stop is out-of-bounds
+    start_time: ServiceTime,
+    stop_sequence: Vec<StopTime>,
+    /// shape_dist_traveled per entry in the stop_sequence
+    shape_dists: Vec<Option<f64>>,
+}
+
+/// Reads Fares v2 if the feed has it, otherwise Fares v1. Rules referring only to things outside
+/// the study area are skipped.
+fn scrape_fares(
+    files: &mut GtfsFiles,
+    stop_ids: &IDMapping<orig_ids::StopID, StopID>,
+    route_ids: &IDMapping<orig_ids::RouteID, RouteID>,
+    zones: BTreeMap<String, BTreeSet<StopID>>,
+    mut networks: BTreeMap<String, Vec<orig_ids::RouteID>>,
+    issues: &mut Vec<GtfsIssue>,
+) -> Result<Vec<FareRule>> {
+    let mut fares = Vec::new();
+
+    if files.exists("fare_leg_rules.txt")? {
+        info!("Scraping Fares v2");
+        // A product may have different prices per fare media; use the cheapest
+        let mut products: BTreeMap<String, (f64, String)> = BTreeMap::new();
+        if files.exists("fare_products.txt")? {
+            for rec in files.csv("fare_products.txt")?.deserialize() {
+                let rec: FareProductRow = rec?;
+                let entry = products
+                    .entry(rec.fare_product_id)
+                    .or_insert((rec.amount, rec.currency.clone()));
+                if rec.amount < entry.0 {
+                    *entry = (rec.amount, rec.currency);
+                }
+            }
+        }
+
+        let mut areas: BTreeMap<String, BTreeSet<StopID>> = BTreeMap::new();
+        if files.exists("stop_areas.txt")? {
+            for rec in files.csv("stop_areas.txt")?.deserialize() {
+                let rec: StopAreaRow = rec?;
+                if let Some(stop) = stop_ids.get(&rec.stop_id) {
+                    areas.entry(rec.area_id).or_default().insert(stop);
+                }
+            }
+        }
+
+        if files.exists("route_networks.txt")? {
+            for rec in files.csv("route_networks.txt")?.deserialize() {
+                let rec: RouteNetworkRow = rec?;
+                networks
+                    .entry(rec.network_id)
+                    .or_default()
+                    .push(rec.route_id);
+            }
+        }
+
+        for rec in files.csv("fare_leg_rules.txt")?.deserialize() {
+            let rec: FareLegRuleRow = rec?;
+            let Some((price, currency)) = products.get(&rec.fare_product_id) else {
+                issues.push(GtfsIssue::UnknownFare {
+                    file: "fare_leg_rules.txt",
+                    fare_id: rec.fare_product_id,
+                });
+                continue;
+            };
+            let (Some(routes), Some(from_stops), Some(to_stops)) = (
+                lookup_filter(rec.network_id, |id| {
+                    networks
+                        .get(id)
+                        .map(|routes| routes.iter().filter_map(|r| route_ids.get(r)).collect())
+                }),
+                lookup_filter(rec.from_area_id, |id| areas.get(id).cloned()),
+                lookup_filter(rec.to_area_id, |id| areas.get(id).cloned()),
+            ) else {
+                continue;
+            };
+            fares.push(FareRule {
+                price: *price,
+                currency: currency.clone(),
+                routes,
+                from_stops,
+                to_stops,
+                priority: rec.rule_priority.unwrap_or(0),
+            });
+        }
+        return Ok(fares);
+    }
+
+    if !files.exists("fare_attributes.txt")? {
+        return Ok(fares);
+    }
+    info!("Scraping Fares v1");
+    let mut attributes: BTreeMap<String, (f64, String)> = BTreeMap::new();
+    for rec in files.csv("fare_attributes.txt")?.deserialize() {
+        let rec: FareAttributeRow = rec?;
+        attributes.insert(rec.fare_id, (rec.price, rec.currency_type));
+    }
+
+    // Without any rules, every fare applies everywhere
+    if !files.exists("fare_rules.txt")? {
+        for (price, currency) in attributes.into_values() {
+            fares.push(FareRule {
+                price,
+                currency,
+                routes: None,
+                from_stops: None,
+                to_stops: None,
+                priority: 0,
+            });
+        }
+        return Ok(fares);
+    }
+
+    let mut skipped = 0;
+    for rec in files.csv("fare_rules.txt")?.deserialize() {
+        let rec: FareRuleRow = rec?;
+        let Some((price, currency)) = attributes.get(&rec.fare_id) else {
+            issues.push(GtfsIssue::UnknownFare {
+                file: "fare_rules.txt",
+                fare_id: rec.fare_id,
+            });
+            continue;
+        };
+        // TODO Handle rules about zones passed through
+        if rec.contains_id.is_some() {
+            skipped += 1;
+            continue;
+        }
+        let (Some(routes), Some(from_stops), Some(to_stops)) = (
+            lookup_filter(rec.route_id, |id| {
+                route_ids.get(id).map(|r| BTreeSet::from([r]))
+            }),
+            lookup_filter(rec.origin_id, |id| zones.get(id).cloned()),
+            lookup_filter(rec.destination_id, |id| zones.get(id).cloned()),
+        ) else {
+            continue;
+        };
+        fares.push(FareRule {
+            price: *price,
+            currency: currency.clone(),
+            routes,
+            from_stops,
+            to_stops,
+            priority: 0,
+        });
+    }
+    if skipped > 0 {
+        warn!("Ignored {skipped} fare_rules.txt entries using contains_id");
+    }
+    Ok(fares)
+}
+
+/// A blank field in a fare rule matches anything, so returns `Some(None)`. If the field refers to
+/// something without anything in the study area, returns `None`, because the rule can't match.
+fn lookup_filter<K, V, F: Fn(&K) -> Option<BTreeSet<V>>>(
+    id: Option<K>,
+    lookup: F,
+) -> Option<Option<BTreeSet<V>>> {
+    let Some(id) = id else {
+        return Some(None);
+    };
+    let set = lookup(&id)?;
+    if set.is_empty() {
+        return None;
+    }
+    Some(Some(set))
+}
+
+fn summarize_issues(issues: &[GtfsIssue]) {
+    // Stops outside the study area are expected when clipping a large feed
+    let count = issues
+        .iter()
+        .filter(|issue| !matches!(issue, GtfsIssue::StopOutOfBounds { .. }))
+        .count();
+    if count > 0 {
+        warn!("Found {count} problems with the GTFS input. Run the validate-gtfs CLI for details.");
+    }
+}
+
+/// `wheelchair_accessible` and `wheelchair_boarding` use 1 for yes, 2 for no, and 0 or blank for
+/// unknown
+fn parse_wheelchair(x: Option<u8>) -> Option<bool> {
+    match x {
+        Some(1) => Some(true),
+        Some(2) => Some(false),
+        _ => None,
+    }
+}
+
+/// GTFS dates are YYYYMMDD
+fn parse_date(x: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(x, "%Y%m%d").map_err(|err| anyhow!("Bad date {x}: {err}"))
+}
+
+/// A trip listed in frequencies.txt is a template. Its times are only used relative to
+/// `template_start`, the arrival time at its first stop. (Strictly, frequencies.txt describes
+/// departures from the first stop, but dwell time there rarely matters.) Expand it into concrete
+/// trips, starting every `headway_secs` from `start_time` until `end_time`.
+fn expand_frequencies(
+    template: Trip,
+    template_start: ServiceTime,
+    rows: &[FrequencyRow],
+    trips: &mut Vec<Trip>,
+    issues: &mut Vec<GtfsIssue>,
+) {
+    // Times going backwards could put a stop before the start
+    let Some(offsets) = template
+        .stop_sequence
+        .iter()
+        .map(|st| {
+            Some((
+                st.arrival.checked_sub(template_start)?,
+                st.departure.checked_sub(template_start)?,
+            ))
+        })
+        .collect::<Option<Vec<_>>>()
+    else {
+        issues.push(GtfsIssue::BadFrequencyTemplate {
+            trip_id: template.orig_id,
+        });
+        return;
+    };
+
+    for rec in rows {
+        let (Ok(mut start_time), Ok(end_time)) = (
+            ServiceTime::parse(&rec.start_time),
+            ServiceTime::parse(&rec.end_time),
+        ) else {
+            issues.push(GtfsIssue::BadFrequency {
+                trip_id: rec.trip_id.clone(),
+            });
+            continue;
+        };
+        if rec.headway_secs == 0 {
+            issues.push(GtfsIssue::BadFrequency {
+                trip_id: rec.trip_id.clone(),
+            });
+            continue;
+        }
+        while start_time < end_time {
+            trips.push(Trip {
+                orig_id: template.orig_id.clone(),
+                start_time,
+                stop_sequence: template
+                    .stop_sequence
+                    .iter()
+                    .zip(&offsets)
+                    .map(|(st, (arrival, departure))| StopTime {
+                        stop: st.stop,
+                        arrival: start_time + *arrival,
+                        departure: start_time + *departure,
+                    })
+                    .collect(),
+                route: template.route,
+                service: template.service,
+                shape: template.shape,
+                wheelchair_accessible: template.wheelchair_accessible,
+            });
+            start_time = start_time + Duration::from_secs(rec.headway_secs.into());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture;
+
+    #[test]
+    fn frequencies() {
+        let (gtfs, issues) = GtfsModel::parse_with_issues(
+            &fixture::gtfs_path("frequencies"),
+            Some(&fixture::study_area()),
+            &mut Timer::new("parse test feed", None),
+        )
+        .unwrap();
+
+        // The template's own times are only used relative to its start, and dwell times are kept
+        let times: Vec<Vec<String>> = gtfs
+            .trips
+            .iter()
+            .map(|trip| {
+                trip.stop_sequence
+                    .iter()
+                    .flat_map(|st| [st.arrival.to_string(), st.departure.to_string()])
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            times,
+            vec![
+                vec!["08:00:00", "08:00:00", "08:05:00", "08:06:00"],
+                vec!["08:20:00", "08:20:00", "08:25:00", "08:26:00"],
+                vec!["08:40:00", "08:40:00", "08:45:00", "08:46:00"],
+            ]
+        );
+
+        // The out-of-bounds first stop is later than the rest, so they're all skipped
+        assert!(issues.iter().any(|issue| matches!(
+            issue,
+            GtfsIssue::TimesNotIncreasing { trip_id, stop_id }
+                if trip_id.as_str() == "BAD" && stop_id.as_str() == "A"
+        )));
+        // Rows with a malformed time or no headway are skipped
+        assert_eq!(
+            issues
+                .iter()
+                .filter(|issue| matches!(
+                    issue,
+                    GtfsIssue::BadFrequency { trip_id } if trip_id.as_str() == "EVERY"
+                ))
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn split_at_gap() {
+        let gtfs = fixture::gtfs("clip");
+
+        // LOOP leaves the area between B and C, so it's split instead of hopping straight between
+        // them. Only the first stop of AWAY is inside.
+        let trips: Vec<(&str, Vec<&str>)> = gtfs
+            .trips
+            .iter()
+            .map(|trip| {
+                (
+                    trip.orig_id.as_str(),
+                    trip.stop_sequence
+                        .iter()
+                        .map(|st| gtfs.stops[st.stop.0].orig_id.as_str())
+                        .collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            trips,
+            vec![
+                ("AWAY", vec!["A"]),
+                ("LOOP", vec!["A", "B"]),
+                ("LOOP", vec!["C", "D"])
+            ]
+        );
+        assert!(gtfs.stops.iter().all(|s| s.orig_id.as_str() != "OUT"));
+    }
 }
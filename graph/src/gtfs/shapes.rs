@@ -0,0 +1,107 @@
+use geo::{Coord, Distance, Euclidean, LineLocatePoint, LineString, Point};
+use utils::LineSplit;
+
+use super::{GtfsModel, Shape, StopID, TripID};
+
+/// A point from shapes.txt, already projected
+pub(crate) struct ShapePoint {
+    pub point: Point,
+    pub dist_traveled: Option<f64>,
+}
+
+impl Shape {
+    /// Takes the points of a shape in order, and every stop visited by trips using it. Stops may
+    /// have `shape_dist_traveled` from stop_times.txt, which is much more reliable than snapping
+    /// to the closest part of the shape, especially when a route loops around.
+    pub(crate) fn new(points: &[ShapePoint], stops: &[(Point, Option<f64>)]) -> Self {
+        let linestring: LineString = points.iter().map(|pt| pt.point).collect();
+
+        // Cumulative length along the linestring per point
+        let mut lengths = vec![0.0];
+        for pair in points.windows(2) {
+            lengths
+                .push(lengths.last().unwrap() + Euclidean.distance(pair[0].point, pair[1].point));
+        }
+        let total = *lengths.last().unwrap();
+
+        let use_dists = points.iter().all(|pt| pt.dist_traveled.is_some())
+            && stops.iter().all(|(_, dist)| dist.is_some());
+        let mut stop_fractions = Vec::new();
+        for (stop_pt, dist) in stops {
+            let fraction = if total == 0.0 {
+                0.0
+            } else if use_dists {
+                length_at_dist(points, &lengths, dist.unwrap()) / total
+            } else {
+                // Vehicles don't go backwards
+                linestring
+                    .line_locate_point(stop_pt)
+                    .unwrap_or(0.0)
+                    .max(stop_fractions.last().cloned().unwrap_or(0.0))
+            };
+            stop_fractions.push(fraction.clamp(0.0, 1.0));
+        }
+
+        Self {
+            linestring,
+            stop_fractions,
+        }
+    }
+}
+
+/// Translates `shape_dist_traveled` into the length along the linestring
+fn length_at_dist(points: &[ShapePoint], lengths: &[f64], dist: f64) -> f64 {
+    let idx = points.partition_point(|pt| pt.dist_traveled.unwrap() <= dist);
+    if idx == 0 {
+        return 0.0;
+    }
+    if idx == points.len() {
+        return *lengths.last().unwrap();
+    }
+    let (dist1, dist2) = (
+        points[idx - 1].dist_traveled.unwrap(),
+        points[idx].dist_traveled.unwrap(),
+    );
+    let pct = if dist2 > dist1 {
+        (dist - dist1) / (dist2 - dist1)
+    } else {
+        0.0
+    };
+    lengths[idx - 1] + pct * (lengths[idx] - lengths[idx - 1])
+}
+
+impl GtfsModel {
+    /// The path `trip` takes from `stop1` to a later stop, `stop2`. Returns `None` if the trip
+    /// has no shape.
+    pub fn trip_geometry(&self, trip: TripID, stop1: StopID, stop2: StopID) -> Option<Vec<Coord>> {
+        let (idx1, idx2) = self.ride_indices(trip, stop1, stop2)?;
+        let shape = &self.shapes[self.trips[trip.0].shape?.0];
+        shape
+            .linestring
+            .line_split_twice(shape.stop_fractions[idx1], shape.stop_fractions[idx2])?
+            .into_second()
+            .map(|ls| ls.0)
+    }
+
+    /// Where riding a trip from `stop1` to `stop2` starts and ends in its stop sequence
+    pub fn ride_indices(
+        &self,
+        trip: TripID,
+        stop1: StopID,
+        stop2: StopID,
+    ) -> Option<(usize, usize)> {
+        let trip = &self.trips[trip.0];
+        // A trip could visit the same stop twice, so look for the closest pair
+        trip.stop_sequence
+            .iter()
+            .enumerate()
+            .filter(|(_, st)| st.stop == stop1)
+            .filter_map(|(idx1, _)| {
+                let idx2 = trip.stop_sequence[idx1 + 1..]
+                    .iter()
+                    .position(|st| st.stop == stop2)?;
+                Some((idx1, idx1 + 1 + idx2))
+            })
+            .min_by_key(|(idx1, idx2)| idx2 - idx1)
+    }
+}
@@ -0,0 +1,227 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::time::Duration;
+
+use geo::{Distance, Haversine, Point};
+
+use super::ids::{IDMapping, orig_ids};
+use super::{
+    Alighting, Entrance, GtfsModel, ServiceTime, Station, StationID, Stop, StopAccess, StopID,
+    StudyArea, TransitOptions,
+};
+
+// 3 mph
+pub(crate) const WALKING_SPEED: f64 = 1.34112;
+
+/// Any row from stops.txt, including stations, entrances, and nodes inside a station
+pub(crate) struct StationPart {
+    pub name: String,
+    pub location_type: u8,
+    pub parent: Option<orig_ids::StopID>,
+    /// In WGS84. Generic nodes and boarding areas might not have one.
+    pub point: Option<Point>,
+    pub wheelchair_boarding: Option<bool>,
+}
+
+/// The graph described by `pathways.txt`, connecting entrances, nodes, and platforms
+#[derive(Default)]
+pub(crate) struct Pathways {
+    forwards: BTreeMap<orig_ids::StopID, Vec<(orig_ids::StopID, Duration)>>,
+    backwards: BTreeMap<orig_ids::StopID, Vec<(orig_ids::StopID, Duration)>>,
+}
+
+impl Pathways {
+    pub fn add(
+        &mut self,
+        from: orig_ids::StopID,
+        to: orig_ids::StopID,
+        time: Duration,
+        bidirectional: bool,
+    ) {
+        if bidirectional {
+            self.add(to.clone(), from.clone(), time, false);
+        }
+        self.forwards
+            .entry(from.clone())
+            .or_default()
+            .push((to.clone(), time));
+        self.backwards.entry(to).or_default().push((from, time));
+    }
+
+    /// The time to walk from `start` to everything reachable, or from everything to `start`
+    fn times(
+        &self,
+        start: &orig_ids::StopID,
+        backwards: bool,
+    ) -> BTreeMap<orig_ids::StopID, Duration> {
+        let edges = if backwards {
+            &self.backwards
+        } else {
+            &self.forwards
+        };
+        let mut times = BTreeMap::new();
+        let mut queue = BinaryHeap::new();
+        queue.push(Reverse((Duration::ZERO, start.clone())));
+        while let Some(Reverse((time, current))) = queue.pop() {
+            if times.contains_key(&current) {
+                continue;
+            }
+            for (next, cost) in edges.get(&current).into_iter().flatten() {
+                if !times.contains_key(next) {
+                    queue.push(Reverse((time + *cost, next.clone())));
+                }
+            }
+            times.insert(current, time);
+        }
+        times
+    }
+}
+
+/// How long it takes to walk in a straight line between two WGS84 points
+pub(crate) fn straight_line_time(pt1: Point, pt2: Point) -> Duration {
+    Duration::from_secs_f64(Haversine.distance(pt1, pt2) / WALKING_SPEED)
+}
+
+/// Groups stops into stations and finds the time to walk between entrances and platforms.
+/// `platform_parents` says which station each stop belongs to, and `parts` has everything from
+/// stops.txt.
+pub(crate) fn build_stations(
+    stops: &mut [Stop],
+    platform_parents: BTreeMap<StopID, orig_ids::StopID>,
+    parts: &BTreeMap<orig_ids::StopID, StationPart>,
+    pathways: &Pathways,
+    area: Option<&StudyArea>,
+) -> Vec<Station> {
+    let mut station_ids: IDMapping<orig_ids::StopID, StationID> = IDMapping::new();
+    let mut stations: Vec<Station> = Vec::new();
+    for (stop_id, parent) in platform_parents {
+        let Some(part) = parts.get(&parent).filter(|part| part.location_type == 1) else {
+            continue;
+        };
+        let Some(point) = part.point else {
+            continue;
+        };
+        let station_id = station_ids.insert_idempotent(&parent);
+        if station_id.0 == stations.len() {
+            stations.push(Station {
+                name: part.name.clone(),
+                orig_id: parent.clone(),
+                point: match area {
+                    Some(area) => area.mercator.to_mercator(&point),
+                    None => point,
+                },
+                platforms: Vec::new(),
+                entrances: Vec::new(),
+            });
+        }
+        stations[station_id.0].platforms.push(stop_id);
+        let stop = &mut stops[stop_id.0];
+        stop.station = Some(station_id);
+        // Platforms with unknown accessibility inherit from the station
+        stop.wheelchair_boarding = stop.wheelchair_boarding.or(part.wheelchair_boarding);
+    }
+
+    // Boarding areas belong to a platform. Reaching one means reaching the platform.
+    let platform_of = |id: &orig_ids::StopID| -> orig_ids::StopID {
+        match parts.get(id) {
+            Some(part) if part.location_type == 4 => part.parent.clone().unwrap_or(id.clone()),
+            _ => id.clone(),
+        }
+    };
+
+    for (entrance_id, part) in parts {
+        if part.location_type != 2 {
+            continue;
+        }
+        let (Some(station_id), Some(entrance_pt)) = (
+            part.parent.as_ref().and_then(|p| station_ids.get(p)),
+            part.point,
+        ) else {
+            continue;
+        };
+        if area.is_some_and(|area| !area.contains(entrance_pt)) {
+            continue;
+        }
+
+        let mut to_platform: BTreeMap<orig_ids::StopID, Duration> = BTreeMap::new();
+        let mut from_platform: BTreeMap<orig_ids::StopID, Duration> = BTreeMap::new();
+        for (result, backwards) in [(&mut to_platform, false), (&mut from_platform, true)] {
+            for (id, time) in pathways.times(entrance_id, backwards) {
+                let platform = platform_of(&id);
+                if result.get(&platform).is_none_or(|x| time < *x) {
+                    result.insert(platform, time);
+                }
+            }
+        }
+
+        let station = &mut stations[station_id.0];
+        let mut platforms = Vec::new();
+        for stop_id in &station.platforms {
+            let stop = &stops[stop_id.0];
+            let walk = || {
+                let stop_pt = parts
+                    .get(&stop.orig_id)
+                    .and_then(|part| part.point)
+                    .unwrap_or(entrance_pt);
+                straight_line_time(entrance_pt, stop_pt)
+            };
+            platforms.push((
+                *stop_id,
+                to_platform.get(&stop.orig_id).cloned().unwrap_or_else(walk),
+                from_platform
+                    .get(&stop.orig_id)
+                    .cloned()
+                    .unwrap_or_else(walk),
+            ));
+        }
+        station.entrances.push(Entrance {
+            name: part.name.clone(),
+            orig_id: entrance_id.clone(),
+            point: match area {
+                Some(area) => area.mercator.to_mercator(&entrance_pt),
+                None => entrance_pt,
+            },
+            // Entrances with unknown accessibility inherit from the station
+            wheelchair_boarding: part.wheelchair_boarding.or_else(|| {
+                part.parent
+                    .as_ref()
+                    .and_then(|p| parts.get(p))
+                    .and_then(|station| station.wheelchair_boarding)
+            }),
+            platforms,
+        });
+    }
+
+    stations
+}
+
+impl GtfsModel {
+    /// Somebody wants to board something at `stop1`, after previously getting off `prev`.
+    /// `reach` says when they could get to the road at each of the stop's access points, if at
+    /// all. Returns the earliest time they could be at the stop, or `None` if `options` don't
+    /// allow reaching it. Staying on the same vehicle or changing within a station doesn't require
+    /// walking back in from the street, so this may be earlier than walking there.
+    pub fn earliest_boarding(
+        &self,
+        prev: Option<Alighting>,
+        stop1: StopID,
+        reach: impl Fn(&StopAccess) -> Option<ServiceTime>,
+        options: &TransitOptions,
+    ) -> Option<ServiceTime> {
+        if let Some(prev) = prev
+            && self.same_station(prev.stop, stop1)
+        {
+            return Some(prev.time);
+        }
+        self.stops[stop1.0]
+            .usable_access(options)
+            .filter_map(|a| Some(reach(a)? + a.enter))
+            .min()
+    }
+
+    fn same_station(&self, stop1: StopID, stop2: StopID) -> bool {
+        stop1 == stop2
+            || (self.stops[stop1.0].station.is_some()
+                && self.stops[stop1.0].station == self.stops[stop2.0].station)
+    }
+}
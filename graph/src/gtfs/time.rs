@@ -0,0 +1,153 @@
+use std::fmt;
+use std::ops::{Add, Sub};
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{NaiveTime, Timelike};
+use serde::{Deserialize, Serialize};
+
+/// A time measured from the start of a service day. Unlike `NaiveTime`, this can go past 24 hours.
+/// GTFS uses this for trips that continue after midnight, and searches starting late in the
+/// evening need it to avoid wrapping around.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ServiceTime(Duration);
+
+/// The length of a normal service day
+pub const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+impl ServiceTime {
+    pub fn from_seconds(seconds: u32) -> Self {
+        Self(Duration::from_secs(seconds.into()))
+    }
+
+    /// Fails if the total number of seconds doesn't fit in a u32
+    pub fn from_hms(hours: u32, minutes: u32, seconds: u32) -> Result<Self> {
+        let Some(total) = hours
+            .checked_mul(3600)
+            .and_then(|x| x.checked_add(minutes.checked_mul(60)?))
+            .and_then(|x| x.checked_add(seconds))
+        else {
+            bail!("Time {hours}:{minutes}:{seconds} is too large");
+        };
+        Ok(Self::from_seconds(total))
+    }
+
+    /// Parses GTFS times like "25:10:00". The hours may exceed 23.
+    pub fn parse(x: &str) -> Result<Self> {
+        let parts = x
+            .trim()
+            .split(':')
+            .map(|part| part.parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()?;
+        if parts.len() != 3 || parts[1] >= 60 || parts[2] >= 60 {
+            bail!("Bad time {x}");
+        }
+        Self::from_hms(parts[0], parts[1], parts[2])
+    }
+
+    /// The time from `other` until this, or `None` if `other` is later
+    pub fn checked_sub(self, other: ServiceTime) -> Option<Duration> {
+        self.0.checked_sub(other.0)
+    }
+
+    /// Parses times in queries, like "08:15". These are always within the first 24 hours.
+    pub fn parse_hh_mm(x: &str) -> Result<Self> {
+        let parts = x
+            .trim()
+            .split(':')
+            .map(|part| part.parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| anyhow!("Bad time {x}, expected HH:MM"))?;
+        if parts.len() != 2 || parts[0] >= 24 || parts[1] >= 60 {
+            bail!("Bad time {x}, expected HH:MM");
+        }
+        Self::from_hms(parts[0], parts[1], 0)
+    }
+
+    /// Rounds down to the whole second
+    pub fn seconds(self) -> u32 {
+        self.0.as_secs() as u32
+    }
+
+    /// Shift by some number of days, returning `None` if the result would be before the start of
+    /// the current service day.
+    pub fn shift_days(self, days: i32) -> Option<Self> {
+        let delta = DAY * days.unsigned_abs();
+        if days >= 0 {
+            Some(Self(self.0 + delta))
+        } else {
+            self.0.checked_sub(delta).map(Self)
+        }
+    }
+}
+
+impl From<NaiveTime> for ServiceTime {
+    fn from(t: NaiveTime) -> Self {
+        Self(Duration::new(
+            t.num_seconds_from_midnight().into(),
+            t.nanosecond(),
+        ))
+    }
+}
+
+impl Add<Duration> for ServiceTime {
+    type Output = ServiceTime;
+
+    fn add(self, other: Duration) -> ServiceTime {
+        ServiceTime(self.0 + other)
+    }
+}
+
+impl Sub for ServiceTime {
+    type Output = Duration;
+
+    fn sub(self, other: ServiceTime) -> Duration {
+        self.0 - other.0
+    }
+}
+
+/// Formats like GTFS, with hours possibly past 24
+impl fmt::Display for ServiceTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let seconds = self.seconds();
+        write!(
+            f,
+            "{:02}:{:02}:{:02}",
+            seconds / 3600,
+            (seconds % 3600) / 60,
+            seconds % 60
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn past_midnight() {
+        let t = ServiceTime::parse("25:10:05").unwrap();
+        assert_eq!(t.seconds(), 25 * 3600 + 10 * 60 + 5);
+        assert_eq!(t.to_string(), "25:10:05");
+        assert_eq!(t.shift_days(-1).unwrap().to_string(), "01:10:05");
+        assert!(t.shift_days(-2).is_none());
+
+        // Late queries don't wrap around
+        let start = ServiceTime::from(NaiveTime::from_hms_opt(23, 45, 0).unwrap());
+        let end = start + Duration::from_secs(30 * 60);
+        assert!(end > start);
+        assert_eq!(end.to_string(), "24:15:00");
+
+        assert!(ServiceTime::parse("").is_err());
+        assert!(ServiceTime::parse("07:61:00").is_err());
+        assert!(ServiceTime::parse("9999999:00:00").is_err());
+        assert!(ServiceTime::from_hms(u32::MAX, 0, 0).is_err());
+
+        assert_eq!(
+            ServiceTime::parse_hh_mm("08:15").unwrap().to_string(),
+            "08:15:00"
+        );
+        assert!(ServiceTime::parse_hh_mm("08:15:00").is_err());
+        assert!(ServiceTime::parse_hh_mm("24:00").is_err());
+    }
+}
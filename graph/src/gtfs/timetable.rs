@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{GtfsModel, NextStep, PatternID, RouteID, ServiceTime, StopID, TripID};
+
+/// Trips along the same route, visiting the same stops in the same order
+#[derive(Serialize, Deserialize)]
+pub struct Pattern {
+    pub route: RouteID,
+    pub stops: Vec<StopID>,
+    /// Sorted by departure from the first stop
+    pub trips: Vec<TripID>,
+}
+
+/// Every time trips following one pattern leave a stop
+#[derive(Serialize, Deserialize)]
+pub struct Departures {
+    pub pattern: PatternID,
+    /// The position of the stop in the pattern. A pattern could visit the same stop twice.
+    pub idx: usize,
+    /// Sorted. Trips can overtake each other, so this might not match the order of the pattern's
+    /// trips.
+    pub times: Vec<ServiceTime>,
+    /// The trip departing at each time
+    pub trips: Vec<TripID>,
+}
+
+impl Departures {
+    /// Indices of everything departing between `start` and `end`, inclusive
+    pub fn between(&self, start: ServiceTime, end: ServiceTime) -> std::ops::Range<usize> {
+        let from = self.times.partition_point(|t| *t < start);
+        let to = self.times.partition_point(|t| *t <= end);
+        from..to.max(from)
+    }
+}
+
+impl GtfsModel {
+    /// Groups trips into patterns and builds the departures from every stop. This must be redone
+    /// after changing any trip.
+    pub(crate) fn build_timetable(&mut self) {
+        self.patterns.clear();
+        for stop in &mut self.stops {
+            stop.departures.clear();
+        }
+
+        let mut pattern_ids: BTreeMap<(RouteID, Vec<StopID>), PatternID> = BTreeMap::new();
+        for (idx, trip) in self.trips.iter().enumerate() {
+            // Cancelled trips have no stops
+            if trip.stop_sequence.len() < 2 {
+                continue;
+            }
+            let key = (
+                trip.route,
+                trip.stop_sequence.iter().map(|st| st.stop).collect(),
+            );
+            let pattern_id = *pattern_ids.entry(key).or_insert_with_key(|(route, stops)| {
+                self.patterns.push(Pattern {
+                    route: *route,
+                    stops: stops.clone(),
+                    trips: Vec::new(),
+                });
+                PatternID(self.patterns.len() - 1)
+            });
+            self.patterns[pattern_id.0].trips.push(TripID(idx));
+        }
+
+        for (idx, pattern) in self.patterns.iter_mut().enumerate() {
+            let trips = &self.trips;
+            pattern
+                .trips
+                .sort_by_key(|t| trips[t.0].stop_sequence[0].departure);
+
+            // The last stop has no departures
+            for stop_idx in 0..pattern.stops.len() - 1 {
+                let mut times: Vec<(ServiceTime, TripID)> = pattern
+                    .trips
+                    .iter()
+                    .map(|t| (trips[t.0].stop_sequence[stop_idx].departure, *t))
+                    .collect();
+                times.sort();
+                self.stops[pattern.stops[stop_idx].0]
+                    .departures
+                    .push(Departures {
+                        pattern: PatternID(idx),
+                        idx: stop_idx,
+                        times: times.iter().map(|(t, _)| *t).collect(),
+                        trips: times.into_iter().map(|(_, t)| t).collect(),
+                    });
+            }
+        }
+    }
+
+    /// The `i`th departure, riding to the next stop in the pattern
+    pub fn next_step(&self, departures: &Departures, i: usize) -> NextStep {
+        let trip = departures.trips[i];
+        let next = &self.trips[trip.0].stop_sequence[departures.idx + 1];
+        NextStep {
+            time1: departures.times[i],
+            trip,
+            pattern: departures.pattern,
+            stop2: next.stop,
+            time2: next.arrival,
+        }
+    }
+
+    /// Every departure from a stop, sorted by time
+    pub fn all_next_steps(&self, stop: StopID) -> Vec<NextStep> {
+        let mut results = Vec::new();
+        for departures in &self.stops[stop.0].departures {
+            for i in 0..departures.times.len() {
+                results.push(self.next_step(departures, i));
+            }
+        }
+        results.sort_by_key(|x| x.time1);
+        results
+    }
+}
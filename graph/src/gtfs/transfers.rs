@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::{GtfsModel, NextStep, ServiceTime, StopID, TransitOptions, TripID};
+
+/// A rule from `transfers.txt` about changing trips between two stops
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum Transfer {
+    /// The departing vehicle waits for the arriving one, so no extra time is needed
+    Timed,
+    /// Changing trips takes at least this long, including any walking
+    MinTime(Duration),
+    /// Changing trips here isn't possible
+    Forbidden,
+}
+
+/// Where and when somebody last got off a trip. Searches carry this around to decide if the next
+/// trip can be boarded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Alighting {
+    pub stop: StopID,
+    pub trip: TripID,
+    pub time: ServiceTime,
+}
+
+impl Alighting {
+    /// Is boarding `next_step` at `stop1` just staying on the same vehicle?
+    pub fn stays_on(&self, stop1: StopID, next_step: &NextStep) -> bool {
+        self.trip == next_step.trip && self.stop == stop1
+    }
+}
+
+impl GtfsModel {
+    /// Can somebody at `stop1` board `next_step`, after previously getting off a trip? Staying on
+    /// the same trip is always fine. Otherwise, rules from `transfers.txt` are used, falling back
+    /// to the default minimum transfer time.
+    pub fn can_board(
+        &self,
+        prev: Option<Alighting>,
+        stop1: StopID,
+        next_step: &NextStep,
+        options: &TransitOptions,
+    ) -> bool {
+        let Some(prev) = prev else {
+            return true;
+        };
+        if prev.stays_on(stop1, next_step) {
+            return true;
+        }
+        let min_time = match self.transfers.get(&(prev.stop, stop1)) {
+            Some(Transfer::Forbidden) => {
+                return false;
+            }
+            Some(Transfer::Timed) => Duration::ZERO,
+            Some(Transfer::MinTime(duration)) => *duration,
+            None => options.min_transfer_time,
+        };
+        next_step.time1 >= prev.time + min_time
+    }
+}
@@ -0,0 +1,142 @@
+use geo::Point;
+use geojson::{Feature, Geometry};
+use serde::Serialize;
+
+use super::ids::orig_ids;
+
+/// A problem found in GTFS input. Parsing skips over the problematic data and keeps going.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "kind")]
+pub enum GtfsIssue {
+    /// Some file refers to a trip not defined in trips.txt
+    UnknownTrip {
+        file: &'static str,
+        trip_id: orig_ids::TripID,
+    },
+    /// A trip refers to a route not defined in routes.txt
+    UnknownRoute {
+        trip_id: orig_ids::TripID,
+        route_id: orig_ids::RouteID,
+    },
+    /// A trip uses a service not defined in calendar.txt or calendar_dates.txt
+    UnknownService {
+        trip_id: orig_ids::TripID,
+        service_id: orig_ids::ServiceID,
+    },
+    /// A trip uses a shape without at least two points in shapes.txt
+    UnknownShape {
+        trip_id: orig_ids::TripID,
+        shape_id: orig_ids::ShapeID,
+    },
+    /// Some file refers to a stop not defined in stops.txt
+    UnknownStop {
+        file: &'static str,
+        stop_id: orig_ids::StopID,
+    },
+    /// A fare rule refers to a fare or fare product that isn't defined
+    UnknownFare { file: &'static str, fare_id: String },
+    /// A stop ID is defined more than once. Only the first is kept.
+    DuplicateStop {
+        stop_id: orig_ids::StopID,
+        /// In WGS84
+        point: Point,
+    },
+    /// A stop is outside the study area
+    StopOutOfBounds {
+        stop_id: orig_ids::StopID,
+        /// In WGS84
+        point: Point,
+    },
+    /// A trip reaches a stop before leaving the previous one, or leaves a stop before arriving.
+    /// The stop is skipped.
+    TimesNotIncreasing {
+        trip_id: orig_ids::TripID,
+        stop_id: orig_ids::StopID,
+    },
+    /// A time in stop_times.txt is neither blank nor HH:MM:SS. The stop is skipped.
+    BadStopTime {
+        trip_id: orig_ids::TripID,
+        stop_id: orig_ids::StopID,
+    },
+    /// A trip in frequencies.txt has a stop earlier than its first arrival, so it can't be used
+    /// as a template. The trip is skipped.
+    BadFrequencyTemplate { trip_id: orig_ids::TripID },
+    /// A row in frequencies.txt has a malformed time or no headway. The row is skipped.
+    BadFrequency { trip_id: orig_ids::TripID },
+}
+
+impl GtfsIssue {
+    /// Only some issues have a location. The properties describe the issue.
+    pub fn to_gj(&self) -> Option<Feature> {
+        let point = match self {
+            GtfsIssue::DuplicateStop { point, .. } | GtfsIssue::StopOutOfBounds { point, .. } => {
+                point
+            }
+            _ => {
+                return None;
+            }
+        };
+        let mut f = Feature::from(Geometry::from(point));
+        if let Ok(serde_json::Value::Object(props)) = serde_json::to_value(self) {
+            for (key, value) in props {
+                if key != "point" {
+                    f.set_property(key, value);
+                }
+            }
+        }
+        Some(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GtfsModel, Timer, fixture};
+
+    #[test]
+    fn broken_feed() {
+        let (gtfs, issues) = GtfsModel::parse_with_issues(
+            &fixture::gtfs_path("broken"),
+            Some(&fixture::study_area()),
+            &mut Timer::new("parse test feed", None),
+        )
+        .unwrap();
+
+        // Summarize each issue by its kind and the trip or stop involved
+        let found: Vec<(String, String)> = issues
+            .iter()
+            .map(|issue| {
+                let props = serde_json::to_value(issue).unwrap();
+                let id = props.get("trip_id").or(props.get("stop_id")).unwrap();
+                (props["kind"].to_string(), id.to_string())
+            })
+            .collect();
+        for (kind, id) in [
+            ("DuplicateStop", "A"),
+            ("StopOutOfBounds", "FAR"),
+            ("UnknownRoute", "BAD_ROUTE"),
+            ("UnknownService", "BAD_SERVICE"),
+            ("UnknownStop", "MISSING"),
+            ("UnknownTrip", "GHOST"),
+            ("TimesNotIncreasing", "BACKWARDS"),
+            ("BadStopTime", "GARBLED"),
+        ] {
+            assert!(
+                found.contains(&(format!("{kind:?}"), format!("{id:?}"))),
+                "no {kind} for {id} in {found:?}"
+            );
+        }
+
+        // The first copy of A is kept, and the trips with bad stops just skip them
+        let stops: Vec<(&str, &str)> = gtfs
+            .stops
+            .iter()
+            .map(|s| (s.orig_id.as_str(), s.name.as_str()))
+            .collect();
+        assert_eq!(stops, vec![("A", "Alpha"), ("B", "Bravo")]);
+        let trips: Vec<&str> = gtfs.trips.iter().map(|t| t.orig_id.as_str()).collect();
+        assert_eq!(trips, vec!["BACKWARDS", "GARBLED", "GOOD", "TYPO"]);
+        let lengths: Vec<usize> = gtfs.trips.iter().map(|t| t.stop_sequence.len()).collect();
+        assert_eq!(lengths, vec![1, 1, 2, 2]);
+    }
+}
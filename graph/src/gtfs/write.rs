@@ -0,0 +1,328 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{Result, bail};
+use chrono::{Datelike, NaiveDate};
+use serde::Serialize;
+use utils::Mercator;
+
+use super::{Agency, GtfsModel, Trip, orig_ids};
+
+impl GtfsModel {
+    /// Writes `agency.txt`, `stops.txt`, `routes.txt`, `trips.txt`, `stop_times.txt`,
+    /// `calendar.txt`, and `calendar_dates.txt` to a directory, using the original IDs. Files with
+    /// no rows are skipped. If the model was parsed with a `StudyArea`, pass in its `Mercator` to
+    /// get stop positions back in WGS84. Fails if any route has no agency.
+    ///
+    /// Stations, transfers, shapes, and fares aren't written. Trips from `frequencies.txt` or split
+    /// at the edge of the study area are written separately, with a suffix added to their IDs.
+    /// Trips with fewer than 2 stops aren't valid GTFS, so they're skipped.
+    pub fn write_gtfs(&self, dir: &str, mercator: Option<&Mercator>) -> Result<()> {
+        let mut agencies: BTreeMap<&orig_ids::AgencyID, &Agency> = BTreeMap::new();
+        for route in &self.routes {
+            let Some(ref agency) = route.agency else {
+                bail!(
+                    "route {} has no agency, so it can't be written",
+                    route.orig_id.as_str()
+                );
+            };
+            agencies.insert(&agency.orig_id, agency);
+        }
+
+        std::fs::create_dir_all(dir)?;
+
+        write_csv(
+            format!("{dir}/agency.txt"),
+            agencies.into_values().map(|agency| AgencyRow {
+                agency_id: &agency.orig_id,
+                agency_name: &agency.name,
+                agency_url: &agency.url,
+                agency_timezone: &agency.timezone,
+            }),
+        )?;
+
+        write_csv(
+            format!("{dir}/stops.txt"),
+            self.stops.iter().map(|stop| {
+                let point = match mercator {
+                    Some(mercator) => mercator.to_wgs84(&stop.point),
+                    None => stop.point,
+                };
+                StopRow {
+                    stop_id: &stop.orig_id,
+                    stop_name: &stop.name,
+                    stop_lat: point.y(),
+                    stop_lon: point.x(),
+                    wheelchair_boarding: write_wheelchair(stop.wheelchair_boarding),
+                }
+            }),
+        )?;
+
+        write_csv(
+            format!("{dir}/routes.txt"),
+            self.routes.iter().map(|route| RouteRow {
+                route_id: &route.orig_id,
+                agency_id: route.agency.as_ref().map(|agency| &agency.orig_id),
+                route_short_name: route.short_name.as_deref(),
+                route_long_name: route.long_name.as_deref(),
+                route_desc: route.description.as_deref(),
+                route_type: route.route_type,
+            }),
+        )?;
+
+        let trips: Vec<&Trip> = self
+            .trips
+            .iter()
+            .filter(|trip| trip.stop_sequence.len() >= 2)
+            .collect();
+
+        // Some trips share the same original ID, but every row needs a different one
+        let mut used: BTreeSet<String> = trips
+            .iter()
+            .map(|trip| trip.orig_id.as_str().to_string())
+            .collect();
+        let mut count: BTreeMap<&orig_ids::TripID, usize> = BTreeMap::new();
+        let mut trip_ids = Vec::new();
+        for trip in &trips {
+            let n = count.entry(&trip.orig_id).or_insert(0);
+            *n += 1;
+            if *n == 1 {
+                trip_ids.push(trip.orig_id.as_str().to_string());
+                continue;
+            }
+            loop {
+                let id = format!("{}_{n}", trip.orig_id.as_str());
+                if used.insert(id.clone()) {
+                    trip_ids.push(id);
+                    break;
+                }
+                *n += 1;
+            }
+        }
+
+        write_csv(
+            format!("{dir}/trips.txt"),
+            trips.iter().zip(&trip_ids).map(|(trip, trip_id)| TripRow {
+                route_id: &self.routes[trip.route.0].orig_id,
+                service_id: &self.services[trip.service.0].orig_id,
+                trip_id,
+                wheelchair_accessible: write_wheelchair(trip.wheelchair_accessible),
+            }),
+        )?;
+
+        write_csv(
+            format!("{dir}/stop_times.txt"),
+            trips.iter().zip(&trip_ids).flat_map(|(trip, trip_id)| {
+                trip.stop_sequence
+                    .iter()
+                    .enumerate()
+                    .map(move |(idx, st)| StopTimeRow {
+                        trip_id,
+                        arrival_time: st.arrival.to_string(),
+                        departure_time: st.departure.to_string(),
+                        stop_id: &self.stops[st.stop.0].orig_id,
+                        stop_sequence: idx + 1,
+                    })
+            }),
+        )?;
+
+        // Services only defined by exceptions just go in calendar_dates.txt
+        write_csv(
+            format!("{dir}/calendar.txt"),
+            self.services.iter().filter_map(|service| {
+                let (start, end) = service.date_range?;
+                let day = |idx: usize| u8::from(service.weekdays[idx]);
+                Some(CalendarRow {
+                    service_id: &service.orig_id,
+                    monday: day(0),
+                    tuesday: day(1),
+                    wednesday: day(2),
+                    thursday: day(3),
+                    friday: day(4),
+                    saturday: day(5),
+                    sunday: day(6),
+                    start_date: write_date(start),
+                    end_date: write_date(end),
+                })
+            }),
+        )?;
+
+        write_csv(
+            format!("{dir}/calendar_dates.txt"),
+            self.services.iter().flat_map(|service| {
+                let added = service.added_dates.iter().map(|date| (*date, 1));
+                let removed = service.removed_dates.iter().map(|date| (*date, 2));
+                added
+                    .chain(removed)
+                    .map(|(date, exception_type)| CalendarDateRow {
+                        service_id: &service.orig_id,
+                        date: write_date(date),
+                        exception_type,
+                    })
+            }),
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Writes a CSV file with a header, unless there are no rows at all
+fn write_csv<T: Serialize>(path: String, rows: impl Iterator<Item = T>) -> Result<()> {
+    let mut rows = rows.peekable();
+    if rows.peek().is_none() {
+        return Ok(());
+    }
+    let mut writer = csv::Writer::from_path(path)?;
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct AgencyRow<'a> {
+    agency_id: &'a orig_ids::AgencyID,
+    agency_name: &'a str,
+    agency_url: &'a str,
+    agency_timezone: &'a str,
+}
+
+#[derive(Serialize)]
+struct StopRow<'a> {
+    stop_id: &'a orig_ids::StopID,
+    stop_name: &'a str,
+    stop_lat: f64,
+    stop_lon: f64,
+    wheelchair_boarding: u8,
+}
+
+#[derive(Serialize)]
+struct RouteRow<'a> {
+    route_id: &'a orig_ids::RouteID,
+    agency_id: Option<&'a orig_ids::AgencyID>,
+    route_short_name: Option<&'a str>,
+    route_long_name: Option<&'a str>,
+    route_desc: Option<&'a str>,
+    route_type: u16,
+}
+
+#[derive(Serialize)]
+struct TripRow<'a> {
+    route_id: &'a orig_ids::RouteID,
+    service_id: &'a orig_ids::ServiceID,
+    trip_id: &'a str,
+    wheelchair_accessible: u8,
+}
+
+#[derive(Serialize)]
+struct StopTimeRow<'a> {
+    trip_id: &'a str,
+    arrival_time: String,
+    departure_time: String,
+    stop_id: &'a orig_ids::StopID,
+    stop_sequence: usize,
+}
+
+#[derive(Serialize)]
+struct CalendarRow<'a> {
+    service_id: &'a orig_ids::ServiceID,
+    monday: u8,
+    tuesday: u8,
+    wednesday: u8,
+    thursday: u8,
+    friday: u8,
+    saturday: u8,
+    sunday: u8,
+    start_date: String,
+    end_date: String,
+}
+
+#[derive(Serialize)]
+struct CalendarDateRow<'a> {
+    service_id: &'a orig_ids::ServiceID,
+    date: String,
+    exception_type: u8,
+}
+
+/// The opposite of `parse_wheelchair`
+fn write_wheelchair(x: Option<bool>) -> u8 {
+    match x {
+        Some(true) => 1,
+        Some(false) => 2,
+        None => 0,
+    }
+}
+
+/// GTFS dates are YYYYMMDD
+fn write_date(date: NaiveDate) -> String {
+    format!("{:04}{:02}{:02}", date.year(), date.month(), date.day())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture;
+
+    #[test]
+    fn round_trip() {
+        let mut gtfs = fixture::gtfs("raptor");
+        // Not valid GTFS, so it shouldn't be written
+        gtfs.trips.push(Trip {
+            orig_id: orig_ids::TripID::new("SHORT"),
+            start_time: gtfs.trips[0].start_time,
+            stop_sequence: gtfs.trips[0].stop_sequence[..1].to_vec(),
+            route: gtfs.trips[0].route,
+            service: gtfs.trips[0].service,
+            shape: None,
+            wheelchair_accessible: None,
+        });
+
+        let dir = std::env::temp_dir().join("write_gtfs_round_trip");
+        let dir = dir.to_str().unwrap();
+        let _ = std::fs::remove_dir_all(dir);
+        gtfs.write_gtfs(dir, Some(&fixture::study_area().mercator))
+            .unwrap();
+        let copy = GtfsModel::parse(
+            dir,
+            Some(&fixture::study_area()),
+            &mut crate::Timer::new("parse written feed", None),
+        )
+        .unwrap();
+
+        let stops = |gtfs: &GtfsModel| {
+            gtfs.stops
+                .iter()
+                .map(|stop| (stop.orig_id.clone(), stop.name.clone()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(stops(&gtfs), stops(&copy));
+        for (stop1, stop2) in gtfs.stops.iter().zip(&copy.stops) {
+            assert!((stop1.point.x() - stop2.point.x()).abs() < 0.01);
+            assert!((stop1.point.y() - stop2.point.y()).abs() < 0.01);
+        }
+
+        for (route1, route2) in gtfs.routes.iter().zip(&copy.routes) {
+            assert_eq!(route1.orig_id, route2.orig_id);
+            assert!(route1.agency.is_some());
+            assert!(route1.agency == route2.agency);
+        }
+
+        let trips = |gtfs: &GtfsModel| {
+            gtfs.trips
+                .iter()
+                .map(|trip| {
+                    (
+                        trip.orig_id.clone(),
+                        trip.route,
+                        trip.stop_sequence
+                            .iter()
+                            .map(|st| (st.stop, st.arrival, st.departure))
+                            .collect::<Vec<_>>(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        gtfs.trips.pop();
+        assert_eq!(trips(&gtfs), trips(&copy));
+    }
+}
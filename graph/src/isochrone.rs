@@ -1,74 +1,301 @@
-use chrono::NaiveTime;
-use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::collections::{BTreeSet, BinaryHeap, HashMap};
 use std::time::Duration;
 
 use utils::PriorityQueueItem;
 
-use crate::{Graph, IntersectionID, ProfileID, RoadID};
+use crate::gtfs::{Alighting, FareSoFar};
+use crate::{Graph, IntersectionID, Position, ProfileID, RoadID, ServiceTime, TransitOptions};
 
 impl Graph {
     // TODO Doesn't account for start/end distance along roads
     /// From a list of start intersections, floods out the graph for a profile until `end_time` is
     /// reached. Returns the time needed to reach each road within that range. This query is not
-    /// precise about positions along a road.
+    /// precise about positions along a road. If `transit` is specified, public transit is also
+    /// used, staying within any maximum fare.
     pub fn get_costs(
         &self,
         starts: Vec<IntersectionID>,
         profile: ProfileID,
-        public_transit: bool,
-        start_time: NaiveTime,
-        end_time: NaiveTime,
+        transit: Option<&TransitOptions>,
+        start_time: ServiceTime,
+        end_time: ServiceTime,
     ) -> HashMap<RoadID, Duration> {
-        let mut visited: HashSet<IntersectionID> = HashSet::new();
-        let mut cost_per_road: HashMap<RoadID, Duration> = HashMap::new();
-        let mut queue: BinaryHeap<PriorityQueueItem<NaiveTime, IntersectionID>> = BinaryHeap::new();
+        let starts: Vec<_> = starts.into_iter().map(|i| (i, start_time)).collect();
+        let mut arrivals = Arrivals::default();
+        self.flood(&starts, profile, transit, end_time, &mut arrivals);
+        arrivals
+            .roads
+            .into_iter()
+            .map(|(r, time)| (r, time - start_time))
+            .collect()
+    }
+
+    /// Like `get_costs`, but for many departure times, each allowed up to `limit`. Returns, per
+    /// road, the time needed from each departure, or `None` if that departure doesn't reach it.
+    ///
+    /// Like rRAPTOR, departures are handled latest first. Leaving earlier and waiting is always
+    /// possible, so if a later departure already reached an intersection sooner, the search for an
+    /// earlier departure stops there and reuses what was found before.
+    pub fn get_costs_range(
+        &self,
+        starts: Vec<IntersectionID>,
+        profile: ProfileID,
+        transit: Option<&TransitOptions>,
+        departures: &[ServiceTime],
+        limit: Duration,
+    ) -> HashMap<RoadID, Vec<Option<Duration>>> {
+        let mut order: Vec<usize> = (0..departures.len()).collect();
+        order.sort_by_key(|idx| std::cmp::Reverse(departures[*idx]));
 
-        for start in starts {
-            queue.push(PriorityQueueItem::new(start_time, start));
+        let mut arrivals = Arrivals::default();
+        let mut results: HashMap<RoadID, Vec<Option<Duration>>> = HashMap::new();
+        for idx in order {
+            let start_time = departures[idx];
+            let end_time = start_time + limit;
+            let starts: Vec<_> = starts.iter().map(|i| (*i, start_time)).collect();
+            self.flood(&starts, profile, transit, end_time, &mut arrivals);
+            for (r, time) in &arrivals.roads {
+                if *time <= end_time {
+                    results
+                        .entry(*r)
+                        .or_insert_with(|| vec![None; departures.len()])[idx] =
+                        Some(*time - start_time);
+                }
+            }
+        }
+        results
+    }
+
+    /// Like `get_costs` without transit, but starting from a position along a road. Returns the
+    /// time needed to walk to each intersection within `limit`.
+    pub(crate) fn walk_from_position(
+        &self,
+        start: Position,
+        profile: ProfileID,
+        limit: Duration,
+    ) -> HashMap<IntersectionID, Duration> {
+        let start_time = ServiceTime::from_seconds(0);
+        let road = &self.roads[start.road.0];
+        let starts: Vec<_> = [road.src_i, road.dst_i]
+            .into_iter()
+            .filter_map(|i| {
+                Some((
+                    i,
+                    start_time + road.cost_to_end(start.fraction_along, i, profile)?,
+                ))
+            })
+            .collect();
+        let mut arrivals = Arrivals::default();
+        self.flood(&starts, profile, None, start_time + limit, &mut arrivals);
+        arrivals
+            .intersections
+            .into_iter()
+            .filter_map(|(i, labels)| {
+                let time = labels.into_iter().map(|(time, _)| time).min()?;
+                Some((i, time - start_time))
+            })
+            .collect()
+    }
+
+    /// Floods out from `starts`, each reached at some time, recording the earliest arrival
+    /// anywhere in `arrivals`. When `arrivals` already reached an intersection at least as early
+    /// and cheaply, the search stops there.
+    fn flood(
+        &self,
+        starts: &[(IntersectionID, ServiceTime)],
+        profile: ProfileID,
+        transit: Option<&TransitOptions>,
+        end_time: ServiceTime,
+        arrivals: &mut Arrivals,
+    ) {
+        let mut queue: BinaryHeap<PriorityQueueItem<ServiceTime, State>> = BinaryHeap::new();
+
+        for (start, time) in starts {
+            queue.push(PriorityQueueItem::new(
+                *time,
+                (*start, None, FareSoFar::default()),
+            ));
         }
 
         while let Some(current) = queue.pop() {
-            if visited.contains(&current.value) {
+            let (current_i, alighting, fare) = current.value;
+            if current.cost > end_time {
                 continue;
             }
-            visited.insert(current.value);
-            if current.cost > end_time {
+            // Without a fare limit, only the earliest arrival matters
+            let paid = match transit {
+                Some(options) if options.max_fare.is_some() => self.gtfs.fare_paid(alighting, fare),
+                _ => 0.0,
+            };
+            let labels = arrivals.intersections.entry(current_i).or_default();
+            if labels
+                .iter()
+                .any(|(time, x)| *time <= current.cost && *x <= paid)
+            {
                 continue;
             }
+            labels.retain(|(time, x)| *time < current.cost || *x < paid);
+            labels.push((current.cost, paid));
 
-            for r in &self.intersections[current.value.0].roads {
+            for r in &self.intersections[current_i.0].roads {
                 let road = &self.roads[r.0];
                 let total_cost = current.cost + road.cost[profile.0];
-                cost_per_road
+                arrivals
+                    .roads
                     .entry(*r)
-                    .or_insert((total_cost - start_time).to_std().unwrap());
+                    .and_modify(|time| *time = (*time).min(total_cost))
+                    .or_insert(total_cost);
 
-                if road.src_i == current.value && road.allows_forwards(profile) {
-                    queue.push(PriorityQueueItem::new(total_cost, road.dst_i));
+                if road.src_i == current_i && road.allows_forwards(profile) {
+                    queue.push(PriorityQueueItem::new(
+                        total_cost,
+                        (road.dst_i, alighting, fare),
+                    ));
                 }
-                if road.dst_i == current.value && road.allows_backwards(profile) {
-                    queue.push(PriorityQueueItem::new(total_cost, road.src_i));
+                if road.dst_i == current_i && road.allows_backwards(profile) {
+                    queue.push(PriorityQueueItem::new(
+                        total_cost,
+                        (road.src_i, alighting, fare),
+                    ));
                 }
 
-                if public_transit {
+                if let Some(options) = transit {
                     for stop1 in &road.stops {
-                        // Find all trips leaving from this step before the end_time
-                        for next_step in self.gtfs.trips_from(
+                        let Some(board_time) = self.gtfs.earliest_boarding(
+                            alighting,
                             *stop1,
-                            current.cost,
-                            (end_time - current.cost).to_std().unwrap(),
-                        ) {
-                            // TODO Awkwardly, arrive at both intersections for the next stop's road
-                            let stop2_road = &self.roads[self.gtfs.stops[next_step.stop2.0].road.0];
-                            for i in [stop2_road.src_i, stop2_road.dst_i] {
-                                queue.push(PriorityQueueItem::new(next_step.time2, i));
+                            |a| {
+                                if a.position.road != *r {
+                                    return None;
+                                }
+                                Some(
+                                    current.cost
+                                        + road.cost_from_end(
+                                            current_i,
+                                            a.position.fraction_along,
+                                            profile,
+                                        )?,
+                                )
+                            },
+                            options,
+                        ) else {
+                            continue;
+                        };
+                        if board_time > end_time {
+                            continue;
+                        }
+                        // Find all trips leaving from this step before the end_time. Later trips
+                        // following an already boarded pattern only arrive later, so there's no
+                        // need to ride them past the next stop.
+                        let mut boarded = BTreeSet::new();
+                        for next_step in
+                            self.gtfs
+                                .trips_from(*stop1, board_time, end_time - board_time, options)
+                        {
+                            if !self.gtfs.can_board(alighting, *stop1, &next_step, options) {
+                                continue;
+                            }
+                            // Get off here or anywhere later on the same vehicle
+                            let stay_on = boarded
+                                .insert(next_step.pattern)
+                                .then(|| self.gtfs.stay_on(&next_step, options))
+                                .into_iter()
+                                .flatten();
+                            for step in std::iter::once(next_step).chain(stay_on) {
+                                if step.time2 > end_time {
+                                    break;
+                                }
+                                let (next_fare, total_fare) =
+                                    self.gtfs.ride_fare(alighting, fare, *stop1, &step);
+                                if options.max_fare.is_some_and(|max| total_fare > max) {
+                                    continue;
+                                }
+                                let arrival = Alighting {
+                                    stop: step.stop2,
+                                    trip: step.trip,
+                                    time: step.time2,
+                                };
+                                self.alight(
+                                    arrival, next_fare, profile, options, arrivals, &mut queue,
+                                );
                             }
                         }
                     }
                 }
             }
         }
+    }
+
+    /// Walks from a stop to the road it's on and both ends of that road
+    fn alight(
+        &self,
+        arrival: Alighting,
+        fare: FareSoFar,
+        profile: ProfileID,
+        options: &TransitOptions,
+        arrivals: &mut Arrivals,
+        queue: &mut BinaryHeap<PriorityQueueItem<ServiceTime, State>>,
+    ) {
+        for access in self.gtfs.stops[arrival.stop.0].usable_access(options) {
+            let pos = access.position;
+            let road = &self.roads[pos.road.0];
+            let exit_time = arrival.time + access.exit;
+
+            // Like walking from an intersection, the road counts as reached once all of it is
+            let farthest = pos.fraction_along.max(1.0 - pos.fraction_along);
+            let road_time = exit_time + road.cost[profile.0].mul_f64(farthest);
+            arrivals
+                .roads
+                .entry(pos.road)
+                .and_modify(|time| *time = (*time).min(road_time))
+                .or_insert(road_time);
+
+            for i in [road.src_i, road.dst_i] {
+                if let Some(walk) = road.cost_to_end(pos.fraction_along, i, profile) {
+                    queue.push(PriorityQueueItem::new(
+                        exit_time + walk,
+                        (i, Some(arrival), fare),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Where the flood is, the last time a trip was used to handle transfers, and the fare so far
+type State = (IntersectionID, Option<Alighting>, FareSoFar);
+
+/// The earliest time anything reached each intersection and road
+#[derive(Default)]
+struct Arrivals {
+    /// Per intersection, the arrival time and fare paid so far. A later arrival is only kept if
+    /// it paid less than every earlier one.
+    intersections: HashMap<IntersectionID, Vec<(ServiceTime, f64)>>,
+    roads: HashMap<RoadID, ServiceTime>,
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::fixture;
 
-        cost_per_road
+    #[test]
+    fn change_at_same_stop() {
+        // B is half an hour from the street. Getting off FAST1 at 08:10 and staying at B for
+        // FAST2 at 08:15 is the only way to reach C before the SLOW trip at 08:40.
+        let graph = fixture::graph("raptor", &[1, 60, 1]);
+        let options = TransitOptions::new(NaiveDate::from_ymd_opt(2024, 3, 4).unwrap());
+        let start_time = ServiceTime::from_hms(7, 59, 0).unwrap();
+        let costs = graph.get_costs(
+            vec![IntersectionID(0)],
+            graph.walking_profile_for_transit.unwrap(),
+            Some(&options),
+            start_time,
+            start_time + Duration::from_secs(60 * 60),
+        );
+        // Arriving at 08:25, then walking along half of C's road
+        assert_eq!(costs[&RoadID(2)], Duration::from_secs(26 * 60 + 30));
     }
 }
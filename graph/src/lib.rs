@@ -4,16 +4,21 @@ extern crate anyhow;
 extern crate log;
 
 mod create;
+#[cfg(test)]
+mod fixture;
 mod gtfs;
 mod isochrone;
 #[cfg(feature = "muv")]
 pub mod muv_profiles;
+mod ptal;
+mod raptor;
 mod route;
 pub mod snap;
 mod timer;
 mod transit_route;
 
 use std::collections::BTreeMap;
+use std::sync::OnceLock;
 use std::time::Duration;
 
 use anyhow::Result;
@@ -25,9 +30,13 @@ use rstar::AABB;
 use serde::{Deserialize, Serialize};
 use utils::{Mercator, Tags};
 
+#[cfg(feature = "gtfs")]
+pub use self::create::MAX_SNAP_DISTANCE;
+pub use self::ptal::ptal_band;
+pub use self::raptor::{Journey, Leg};
 pub use self::route::{Route, Router};
 pub use self::timer::Timer;
-pub use crate::gtfs::GtfsModel;
+pub use crate::gtfs::{GtfsIssue, GtfsModel, ServiceTime, StudyArea, TransitMode, TransitOptions};
 use crate::gtfs::{StopID, TripID};
 
 /// A study area imported from OpenStreetMap.
@@ -50,6 +59,10 @@ pub struct Graph {
     pub timestamp: Option<i64>,
 
     pub gtfs: GtfsModel,
+    /// Per stop, other stops within walking distance, with and without step-free access. Filled
+    /// out the first time `transit_journeys` needs them.
+    #[serde(skip)]
+    footpaths: [OnceLock<Vec<Vec<(StopID, Duration)>>>; 2],
 }
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
@@ -118,8 +131,8 @@ impl Graph {
             f.set_property("roads", i.roads.iter().map(|r| r.0).collect::<Vec<_>>());
             features.push(f);
         }
-        for s in &self.gtfs.stops {
-            features.push(s.to_gj(&self.mercator));
+        for idx in 0..self.gtfs.stops.len() {
+            features.push(self.gtfs.stop_to_gj(StopID(idx), &self.mercator));
         }
 
         FeatureCollection {
@@ -176,18 +189,7 @@ impl Graph {
             .nearest_neighbor(&pt.into())
             .unwrap()
             .data;
-        let road = &self.roads[r.0];
-        let fraction_along = road.linestring.line_locate_point(&pt.into()).unwrap();
-        let intersection = if fraction_along <= 0.5 {
-            road.src_i
-        } else {
-            road.dst_i
-        };
-        Position {
-            road: road.id,
-            fraction_along,
-            intersection,
-        }
+        self.roads[r.0].closest_position(pt.into())
     }
 
     /// Given a point (in Mercator) and profile, snap to a position along all nearby roads that
@@ -208,27 +210,15 @@ impl Graph {
             .locate_in_envelope_intersecting(&aabb)
         {
             let road = &self.roads[obj.data.0];
-            let fraction_along = road.linestring.line_locate_point(&pt.into()).unwrap();
-            let intersection = if fraction_along <= 0.5 {
-                road.src_i
-            } else {
-                road.dst_i
-            };
+            let pos = road.closest_position(pt.into());
 
             let snapped_pt = road
                 .linestring
-                .point_at_ratio_from_start(&Euclidean, fraction_along)
+                .point_at_ratio_from_start(&Euclidean, pos.fraction_along)
                 .unwrap();
             let distance: f64 = Euclidean.distance(pt, Coord::from(snapped_pt));
 
-            candidates.push((
-                Position {
-                    road: road.id,
-                    fraction_along,
-                    intersection,
-                },
-                (100.0 * distance).round() as usize,
-            ));
+            candidates.push((pos, (100.0 * distance).round() as usize));
         }
 
         candidates.sort_by_key(|(_, dist)| *dist);
@@ -271,6 +261,70 @@ impl Road {
         )
     }
 
+    /// The position along this road closest to a point (in Mercator)
+    pub fn closest_position(&self, pt: Point) -> Position {
+        let fraction_along = self.linestring.line_locate_point(&pt).unwrap();
+        let intersection = if fraction_along <= 0.5 {
+            self.src_i
+        } else {
+            self.dst_i
+        };
+        Position {
+            road: self.id,
+            fraction_along,
+            intersection,
+        }
+    }
+
+    /// The time for this profile to go from intersection `i` at one end of this road to
+    /// `fraction_along` it, if the road can be crossed in that direction
+    pub fn cost_from_end(
+        &self,
+        i: IntersectionID,
+        fraction_along: f64,
+        profile: ProfileID,
+    ) -> Option<Duration> {
+        let (fraction, allowed) = if i == self.src_i {
+            (fraction_along, self.allows_forwards(profile))
+        } else {
+            (1.0 - fraction_along, self.allows_backwards(profile))
+        };
+        allowed.then(|| self.cost[profile.0].mul_f64(fraction))
+    }
+
+    /// The time for this profile to go from `fraction_along` this road to intersection `i` at one
+    /// end, if the road can be crossed in that direction
+    pub fn cost_to_end(
+        &self,
+        fraction_along: f64,
+        i: IntersectionID,
+        profile: ProfileID,
+    ) -> Option<Duration> {
+        let (fraction, allowed) = if i == self.dst_i {
+            (1.0 - fraction_along, self.allows_forwards(profile))
+        } else {
+            (fraction_along, self.allows_backwards(profile))
+        };
+        allowed.then(|| self.cost[profile.0].mul_f64(fraction))
+    }
+
+    /// The time for this profile to go between two fractions along this road, if the road can be
+    /// crossed in that direction
+    pub fn cost_between(
+        &self,
+        fraction_along1: f64,
+        fraction_along2: f64,
+        profile: ProfileID,
+    ) -> Option<Duration> {
+        let allowed = if fraction_along1 <= fraction_along2 {
+            self.allows_forwards(profile)
+        } else {
+            self.allows_backwards(profile)
+        };
+        let fraction = (fraction_along2 - fraction_along1).abs();
+        allowed.then(|| self.cost[profile.0].mul_f64(fraction))
+    }
+
     pub fn to_gj(&self, graph: &Graph) -> Feature {
         let mut f = graph.mercator.to_wgs84_gj(&self.linestring);
         // TODO Rethink most of this -- it's debug info
@@ -289,7 +343,7 @@ impl Road {
 }
 
 /// A position along a road, along with the closest intersection
-#[derive(Clone, Debug, Copy, PartialEq)]
+#[derive(Clone, Debug, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Position {
     pub road: RoadID,
     pub fraction_along: f64,
@@ -297,9 +351,16 @@ pub struct Position {
 }
 
 pub enum GtfsSource {
-    Dir(String),
+    /// A path to a GTFS directory or zip file
+    Path(String),
+    /// The bytes of a zipped GTFS feed
+    Bytes(Vec<u8>),
+    /// A URL to a geomedea file built from GTFS. Only fares are left out.
     Geomedea(String),
-    None,
+    /// A path to a local geomedea file
+    GeomedeaFile(String),
+    /// The bytes of a geomedea file
+    GeomedeaBytes(Vec<u8>),
 }
 
 /// A single step along a route
@@ -0,0 +1,224 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use anyhow::Result;
+use geojson::{Feature, GeoJson, Geometry};
+
+use crate::gtfs::{RouteID, StopID};
+use crate::{Graph, ProfileID, RoadID, ServiceTime, TransitMode, TransitOptions};
+
+impl Graph {
+    /// Calculates the Public Transport Accessibility Level (PTAL) access index for roads, using
+    /// departures between `start_time` and `end_time`. This follows TfL's method:
+    ///
+    /// - Stops are used within an 8 minute walk for buses and coaches, or 12 minutes otherwise.
+    /// - The total access time for a route at a stop is the walk, plus half the time between
+    ///   departures, plus an allowance for reliability. Only the stop with the lowest total counts.
+    /// - Each route's equivalent doorstep frequency is 30 / total access time. Per mode, the best
+    ///   route counts fully and the rest count half. The access index sums these over modes.
+    ///
+    /// Roads without any service nearby are left out, meaning an index of 0. Use `ptal_band` to
+    /// group the index.
+    pub fn ptal(
+        &self,
+        options: &TransitOptions,
+        start_time: ServiceTime,
+        end_time: ServiceTime,
+    ) -> Result<HashMap<RoadID, f64>> {
+        let Some(profile) = self.walking_profile_for_transit else {
+            bail!("public transit hasn't been set up");
+        };
+        if end_time <= start_time {
+            bail!("end_time must be after start_time");
+        }
+        let hours = (end_time - start_time).as_secs_f64() / 3600.0;
+
+        // Per road and route, the lowest total access time in minutes
+        let mut best: HashMap<RoadID, BTreeMap<RouteID, f64>> = HashMap::new();
+        for idx in 0..self.gtfs.stops.len() {
+            let stop = StopID(idx);
+            let mut departures: BTreeMap<RouteID, usize> = BTreeMap::new();
+            for next_step in self
+                .gtfs
+                .trips_from(stop, start_time, end_time - start_time, options)
+            {
+                if next_step.time1 < end_time {
+                    *departures
+                        .entry(self.gtfs.trips[next_step.trip.0].route)
+                        .or_insert(0) += 1;
+                }
+            }
+            let Some(limit) = departures
+                .keys()
+                .map(|route| max_walk(self.gtfs.routes[route.0].mode()))
+                .max()
+            else {
+                continue;
+            };
+
+            for (road, walk) in self.walk_to_stop(stop, profile, options, limit) {
+                for (route, count) in &departures {
+                    let mode = self.gtfs.routes[route.0].mode();
+                    if walk > max_walk(mode) {
+                        continue;
+                    }
+                    let wait = 0.5 * 60.0 / (*count as f64 / hours) + reliability_mins(mode);
+                    let total = walk.as_secs_f64() / 60.0 + wait;
+                    let entry = best.entry(road).or_default().entry(*route).or_insert(total);
+                    *entry = entry.min(total);
+                }
+            }
+        }
+
+        Ok(best
+            .into_iter()
+            .map(|(road, routes)| {
+                let mut per_mode: BTreeMap<TransitMode, Vec<f64>> = BTreeMap::new();
+                for (route, total) in routes {
+                    per_mode
+                        .entry(self.gtfs.routes[route.0].mode())
+                        .or_default()
+                        .push(30.0 / total);
+                }
+                let index = per_mode
+                    .into_values()
+                    .map(|mut frequencies| {
+                        frequencies.sort_by(|a, b| b.total_cmp(a));
+                        frequencies[0] + 0.5 * frequencies[1..].iter().sum::<f64>()
+                    })
+                    .sum();
+                (road, index)
+            })
+            .collect())
+    }
+
+    /// Like `ptal`, but returns GeoJSON roads with the access index and band
+    pub fn ptal_gj(
+        &self,
+        options: &TransitOptions,
+        start_time: ServiceTime,
+        end_time: ServiceTime,
+    ) -> Result<String> {
+        let mut features = Vec::new();
+        for (r, index) in self.ptal(options, start_time, end_time)? {
+            let mut f = Feature::from(Geometry::from(
+                &self.mercator.to_wgs84(&self.roads[r.0].linestring),
+            ));
+            f.set_property("access_index", index);
+            f.set_property("band", ptal_band(index));
+            features.push(f);
+        }
+        Ok(serde_json::to_string(&GeoJson::from(features))?)
+    }
+
+    /// The time to walk from each nearby road to a stop, up to `limit`. Walking is assumed to take
+    /// as long in both directions.
+    fn walk_to_stop(
+        &self,
+        stop: StopID,
+        profile: ProfileID,
+        options: &TransitOptions,
+        limit: Duration,
+    ) -> HashMap<RoadID, Duration> {
+        let mut results: HashMap<RoadID, Duration> = HashMap::new();
+        for access in self.gtfs.stops[stop.0].usable_access(options) {
+            let pos = access.position;
+            let Some(partial) =
+                self.roads[pos.road.0].cost_from_end(pos.intersection, pos.fraction_along, profile)
+            else {
+                continue;
+            };
+            let offset = partial + access.enter;
+            if offset > limit {
+                continue;
+            }
+
+            let start = ServiceTime::from_seconds(0);
+            for (r, cost) in self.get_costs(
+                vec![pos.intersection],
+                profile,
+                None,
+                start,
+                start + (limit - offset),
+            ) {
+                let walk = offset + cost;
+                if walk <= limit {
+                    results
+                        .entry(r)
+                        .and_modify(|x| *x = (*x).min(walk))
+                        .or_insert(walk);
+                }
+            }
+        }
+        results
+    }
+}
+
+/// Groups a PTAL access index into TfL's bands, from "0" for the worst access through "1a", "1b",
+/// "2", "3", "4", "5", "6a", and "6b" for the best.
+pub fn ptal_band(access_index: f64) -> &'static str {
+    let bands = [
+        (0.0, "0"),
+        (2.5, "1a"),
+        (5.0, "1b"),
+        (10.0, "2"),
+        (15.0, "3"),
+        (20.0, "4"),
+        (25.0, "5"),
+        (40.0, "6a"),
+    ];
+    bands
+        .into_iter()
+        .find(|(max, _)| access_index <= *max)
+        .map_or("6b", |(_, band)| band)
+}
+
+/// How far people walk to reach a service, following TfL's 640m for buses and 960m for rail at
+/// 80m per minute
+fn max_walk(mode: TransitMode) -> Duration {
+    match mode {
+        TransitMode::Bus | TransitMode::Coach => Duration::from_secs(8 * 60),
+        _ => Duration::from_secs(12 * 60),
+    }
+}
+
+/// Added to the average wait, since services don't run perfectly on time
+fn reliability_mins(mode: TransitMode) -> f64 {
+    match mode {
+        TransitMode::Bus | TransitMode::Coach => 2.0,
+        _ => 0.75,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn band_edges() {
+        for (index, band) in [
+            (0.0, "0"),
+            (0.01, "1a"),
+            (2.5, "1a"),
+            (2.51, "1b"),
+            (5.0, "1b"),
+            (10.0, "2"),
+            (15.0, "3"),
+            (20.0, "4"),
+            (25.0, "5"),
+            (40.0, "6a"),
+            (40.01, "6b"),
+        ] {
+            assert_eq!(ptal_band(index), band, "index {index}");
+        }
+    }
+
+    #[test]
+    fn walk_limits() {
+        assert_eq!(max_walk(TransitMode::Bus), Duration::from_secs(8 * 60));
+        assert_eq!(max_walk(TransitMode::Coach), Duration::from_secs(8 * 60));
+        for mode in [TransitMode::Rail, TransitMode::Metro, TransitMode::Tram] {
+            assert_eq!(max_walk(mode), Duration::from_secs(12 * 60));
+        }
+    }
+}
@@ -0,0 +1,564 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use rstar::RTree;
+use rstar::primitives::GeomWithData;
+use serde::Serialize;
+
+use crate::gtfs::{PatternID, RouteID, StopID, Transfer, TripID};
+use crate::{Graph, IntersectionID, PathStep, Position, ProfileID, ServiceTime, TransitOptions};
+
+/// How far apart two stops can be to walk between them when changing trips, in meters
+const MAX_FOOTPATH_DISTANCE: f64 = 500.0;
+
+/// The longest somebody will walk from the start to the first stop, or from the last stop to the
+/// end
+const MAX_ACCESS_WALK: Duration = Duration::from_secs(20 * 60);
+
+/// One way to get somewhere using public transit
+#[derive(Serialize)]
+pub struct Journey {
+    pub departure: ServiceTime,
+    pub arrival: ServiceTime,
+    /// How many times somebody changes between trips
+    pub transfers: usize,
+    pub legs: Vec<Leg>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "kind")]
+pub enum Leg {
+    /// Walking from the start or a stop to another stop or the end. The time may include waiting
+    /// for a minimum transfer time.
+    Walk {
+        /// `None` means the start
+        from: Option<StopID>,
+        /// `None` means the end
+        to: Option<StopID>,
+        time1: ServiceTime,
+        time2: ServiceTime,
+    },
+    Transit {
+        trip: TripID,
+        route: RouteID,
+        stop1: StopID,
+        stop2: StopID,
+        time1: ServiceTime,
+        time2: ServiceTime,
+    },
+}
+
+// Arriving at a stop by riding a trip
+#[derive(Clone, Copy)]
+struct Ride {
+    arrival: ServiceTime,
+    trip: TripID,
+    board_stop: StopID,
+    departure: ServiceTime,
+}
+
+// When somebody can board a trip at a stop
+#[derive(Clone, Copy)]
+struct Ready {
+    time: ServiceTime,
+    // `None` means walking from the start. Otherwise, they got off a trip at this stop and maybe
+    // walked from there.
+    via: Option<StopID>,
+}
+
+impl Graph {
+    /// Finds the fastest way to get between two positions using public transit, for each number
+    /// of transfers. Only journeys arriving earlier than every journey with fewer transfers are
+    /// returned, including a journey only walking. This uses RAPTOR over route patterns, so
+    /// unlike `transit_route_gj`, there's no heuristic. Fails if `options.max_fare` is set, since
+    /// fares aren't handled.
+    ///
+    /// TODO Trips from the previous or next service day aren't used yet.
+    pub fn transit_journeys(
+        &self,
+        start: Position,
+        end: Position,
+        options: &TransitOptions,
+        start_time: ServiceTime,
+        max_transfers: usize,
+    ) -> Result<Vec<Journey>> {
+        let Some(profile) = self.walking_profile_for_transit else {
+            bail!("public transit hasn't been set up");
+        };
+        if options.max_fare.is_some() {
+            bail!("journeys can't be limited by fare yet");
+        }
+        let gtfs = &self.gtfs;
+        let num_stops = gtfs.stops.len();
+        let footpaths = self.footpaths(options, profile);
+
+        let from_start = self.walk_from_position(start, profile, MAX_ACCESS_WALK);
+        // Assume walking to the end takes as long as walking from it
+        let to_end = self.walk_from_position(end, profile, MAX_ACCESS_WALK);
+        // Walk from `from` to some intersections, then along part of the road to `to`, or just
+        // along the road if they share one
+        let walk_between =
+            |from: Position, costs: &HashMap<IntersectionID, Duration>, to: Position| {
+                let road = &self.roads[to.road.0];
+                let direct = if from.road == to.road {
+                    road.cost_between(from.fraction_along, to.fraction_along, profile)
+                } else {
+                    None
+                };
+                [road.src_i, road.dst_i]
+                    .into_iter()
+                    .filter_map(|i| {
+                        Some(*costs.get(&i)? + road.cost_from_end(i, to.fraction_along, profile)?)
+                    })
+                    .chain(direct)
+                    .min()
+                    .filter(|walk| *walk <= MAX_ACCESS_WALK)
+            };
+        let walk_times =
+            |from: Position, costs: &HashMap<IntersectionID, Duration>, entering: bool| {
+                gtfs.stops
+                    .iter()
+                    .map(|stop| {
+                        stop.usable_access(options)
+                            .filter_map(|a| {
+                                let walk = walk_between(from, costs, a.position)?;
+                                Some(walk + if entering { a.enter } else { a.exit })
+                            })
+                            .min()
+                    })
+                    .collect::<Vec<_>>()
+            };
+        let access = walk_times(start, &from_start, true);
+        let egress = walk_times(end, &to_end, false);
+
+        let mut journeys = Vec::new();
+        // The earliest arrival at the end so far, used to prune
+        let mut best_total: Option<ServiceTime> = None;
+        if let Some(walk) = walk_between(start, &from_start, end) {
+            let arrival = start_time + walk;
+            best_total = Some(arrival);
+            journeys.push(Journey {
+                departure: start_time,
+                arrival,
+                transfers: 0,
+                legs: vec![Leg::Walk {
+                    from: None,
+                    to: None,
+                    time1: start_time,
+                    time2: arrival,
+                }],
+            });
+        }
+
+        // Indexed by round, then StopID. Round k uses k trips.
+        let mut rides: Vec<Vec<Option<Ride>>> = vec![vec![None; num_stops]];
+        let mut ready: Vec<Vec<Option<Ready>>> = vec![
+            access
+                .iter()
+                .map(|walk| {
+                    walk.map(|walk| Ready {
+                        time: start_time + walk,
+                        via: None,
+                    })
+                })
+                .collect(),
+        ];
+        // The earliest arrival at each stop in any round
+        let mut best_arrival: Vec<Option<ServiceTime>> = vec![None; num_stops];
+
+        for round in 1..=max_transfers + 1 {
+            let prev_ready = &ready[round - 1];
+
+            // Only scan patterns from the earliest stop where somebody could newly board
+            let mut patterns: BTreeMap<PatternID, usize> = BTreeMap::new();
+            for (idx, r) in prev_ready.iter().enumerate() {
+                if r.is_none() {
+                    continue;
+                }
+                for departures in &gtfs.stops[idx].departures {
+                    let first = patterns.entry(departures.pattern).or_insert(departures.idx);
+                    *first = (*first).min(departures.idx);
+                }
+            }
+
+            let mut round_rides: Vec<Option<Ride>> = vec![None; num_stops];
+            for (pattern_id, first) in patterns {
+                let pattern = &gtfs.patterns[pattern_id.0];
+                if options
+                    .modes
+                    .as_ref()
+                    .is_some_and(|modes| !modes.contains(&gtfs.routes[pattern.route.0].mode()))
+                {
+                    continue;
+                }
+
+                // The trip currently being ridden, where it was boarded, and when
+                let mut current: Option<(TripID, StopID, ServiceTime)> = None;
+                for idx in first..pattern.stops.len() {
+                    let stop = pattern.stops[idx];
+
+                    if let Some((trip, board_stop, departure)) = current {
+                        let arrival = gtfs.trips[trip.0].stop_sequence[idx].arrival;
+                        if (!options.wheelchair
+                            || gtfs.stops[stop.0].wheelchair_boarding == Some(true))
+                            && best_arrival[stop.0].is_none_or(|x| arrival < x)
+                            && best_total.is_none_or(|x| arrival < x)
+                        {
+                            best_arrival[stop.0] = Some(arrival);
+                            round_rides[stop.0] = Some(Ride {
+                                arrival,
+                                trip,
+                                board_stop,
+                                departure,
+                            });
+                        }
+                    }
+
+                    // Could somebody catch an earlier trip here?
+                    let Some(r) = prev_ready[stop.0] else {
+                        continue;
+                    };
+                    if idx + 1 == pattern.stops.len() {
+                        continue;
+                    }
+                    if let Some((trip, departure)) =
+                        self.earliest_trip(pattern_id, idx, stop, r.time, options)
+                        && current.is_none_or(|(current_trip, _, _)| {
+                            trip != current_trip
+                                && departure
+                                    < gtfs.trips[current_trip.0].stop_sequence[idx].departure
+                        })
+                    {
+                        current = Some((trip, stop, departure));
+                    }
+                }
+            }
+
+            // Is getting off anywhere and walking to the end better than before?
+            let mut best_egress: Option<(StopID, ServiceTime)> = None;
+            for (idx, ride) in round_rides.iter().enumerate() {
+                if let (Some(ride), Some(walk)) = (ride, egress[idx]) {
+                    let arrival = ride.arrival + walk;
+                    if best_egress.is_none_or(|(_, x)| arrival < x) {
+                        best_egress = Some((StopID(idx), arrival));
+                    }
+                }
+            }
+
+            // Change trips at the same stop, or walk to another
+            let mut round_ready: Vec<Option<Ready>> = vec![None; num_stops];
+            for (idx, ride) in round_rides.iter().enumerate() {
+                let Some(ride) = ride else {
+                    continue;
+                };
+                let stop1 = StopID(idx);
+                let mut consider = |stop2: StopID, walk: Duration| {
+                    let wait = match gtfs.transfers.get(&(stop1, stop2)) {
+                        Some(Transfer::Forbidden) => {
+                            return;
+                        }
+                        Some(Transfer::Timed) => walk,
+                        Some(Transfer::MinTime(min)) => walk.max(*min),
+                        None if stop1 == stop2 => options.min_transfer_time,
+                        None => walk.max(options.min_transfer_time),
+                    };
+                    let time = ride.arrival + wait;
+                    if best_total.is_some_and(|x| time >= x) {
+                        return;
+                    }
+                    if round_ready[stop2.0].is_none_or(|r| time < r.time) {
+                        round_ready[stop2.0] = Some(Ready {
+                            time,
+                            via: Some(stop1),
+                        });
+                    }
+                };
+                consider(stop1, Duration::ZERO);
+                for (stop2, walk) in &footpaths[idx] {
+                    consider(*stop2, *walk);
+                }
+            }
+
+            rides.push(round_rides);
+            ready.push(round_ready);
+
+            if let Some((stop, arrival)) = best_egress
+                && best_total.is_none_or(|x| arrival < x)
+            {
+                best_total = Some(arrival);
+                // Walking and riding one trip both have no transfers, so only keep the faster
+                if round == 1 {
+                    journeys.clear();
+                }
+                journeys.push(build_journey(
+                    self, &rides, &ready, round, stop, arrival, start_time,
+                ));
+            }
+
+            if ready[round].iter().all(|r| r.is_none()) {
+                break;
+            }
+        }
+
+        Ok(journeys)
+    }
+
+    /// For every stop, other stops within walking distance and the time to walk there. These are
+    /// only needed here, so they're found the first time they're used. With `options.wheelchair`,
+    /// only step-free ways in and out of stops are used.
+    fn footpaths(
+        &self,
+        options: &TransitOptions,
+        profile: ProfileID,
+    ) -> &[Vec<(StopID, Duration)>] {
+        self.footpaths[usize::from(options.wheelchair)]
+            .get_or_init(|| self.find_footpaths(options, profile))
+    }
+
+    /// For every stop, finds other stops within walking distance, using the router for `profile`
+    fn find_footpaths(
+        &self,
+        options: &TransitOptions,
+        profile: ProfileID,
+    ) -> Vec<Vec<(StopID, Duration)>> {
+        let gtfs = &self.gtfs;
+        let rtree = RTree::bulk_load(
+            gtfs.stops
+                .iter()
+                .enumerate()
+                .map(|(idx, stop)| GeomWithData::new([stop.point.x(), stop.point.y()], StopID(idx)))
+                .collect(),
+        );
+        let router = &self.routers[profile.0];
+        // Route between the closest intersections to each stop, plus the part of each road used
+        let walk = |p1: &Position, p2: &Position| -> Option<Duration> {
+            let (road1, road2) = (&self.roads[p1.road.0], &self.roads[p2.road.0]);
+            if p1.road == p2.road {
+                return road1.cost_between(p1.fraction_along, p2.fraction_along, profile);
+            }
+            let (i1, i2) = (p1.intersection, p2.intersection);
+            let leave = road1.cost_to_end(p1.fraction_along, i1, profile)?;
+            let arrive = road2.cost_from_end(i2, p2.fraction_along, profile)?;
+            if i1 == i2 {
+                return Some(leave + arrive);
+            }
+            let route = router.route_between_intersections(self, i1, i2).ok()?;
+            Some(
+                leave
+                    + route
+                        .steps
+                        .iter()
+                        .map(|step| match step {
+                            PathStep::Road { road, .. } => self.roads[road.0].cost[profile.0],
+                            PathStep::Transit { .. } => Duration::ZERO,
+                        })
+                        .sum::<Duration>()
+                    + arrive,
+            )
+        };
+
+        let mut results = Vec::new();
+        for (idx, stop1) in gtfs.stops.iter().enumerate() {
+            let mut footpaths = Vec::new();
+            for nearby in rtree.locate_within_distance(
+                [stop1.point.x(), stop1.point.y()],
+                MAX_FOOTPATH_DISTANCE * MAX_FOOTPATH_DISTANCE,
+            ) {
+                let stop2 = &gtfs.stops[nearby.data.0];
+                if nearby.data.0 == idx {
+                    continue;
+                }
+                let mut best: Option<Duration> = None;
+                for a1 in stop1.usable_access(options) {
+                    for a2 in stop2.usable_access(options) {
+                        if let Some(time) = walk(&a1.position, &a2.position) {
+                            let time = a1.exit + time + a2.enter;
+                            if best.is_none_or(|x| time < x) {
+                                best = Some(time);
+                            }
+                        }
+                    }
+                }
+                if let Some(time) = best {
+                    footpaths.push((nearby.data, time));
+                }
+            }
+            footpaths.sort();
+            results.push(footpaths);
+        }
+        results
+    }
+
+    /// The first trip following a pattern that somebody could board at the `idx`th stop after
+    /// `time`
+    fn earliest_trip(
+        &self,
+        pattern: PatternID,
+        idx: usize,
+        stop: StopID,
+        time: ServiceTime,
+        options: &TransitOptions,
+    ) -> Option<(TripID, ServiceTime)> {
+        let gtfs = &self.gtfs;
+        if options.wheelchair && gtfs.stops[stop.0].wheelchair_boarding != Some(true) {
+            return None;
+        }
+        let departures = gtfs.stops[stop.0]
+            .departures
+            .iter()
+            .find(|d| d.pattern == pattern && d.idx == idx)?;
+        let from = departures.times.partition_point(|t| *t < time);
+        (from..departures.times.len()).find_map(|i| {
+            let trip = &gtfs.trips[departures.trips[i].0];
+            (gtfs.runs_on(trip.service, options.date)
+                && (!options.wheelchair || trip.wheelchair_accessible == Some(true)))
+            .then_some((departures.trips[i], departures.times[i]))
+        })
+    }
+}
+
+/// Works backwards from getting off at `stop` in some round
+fn build_journey(
+    graph: &Graph,
+    rides: &[Vec<Option<Ride>>],
+    ready: &[Vec<Option<Ready>>],
+    mut round: usize,
+    mut stop: StopID,
+    arrival: ServiceTime,
+    start_time: ServiceTime,
+) -> Journey {
+    let transfers = round - 1;
+    let mut legs = vec![Leg::Walk {
+        from: Some(stop),
+        to: None,
+        time1: rides[round][stop.0].unwrap().arrival,
+        time2: arrival,
+    }];
+    loop {
+        let ride = rides[round][stop.0].unwrap();
+        legs.push(Leg::Transit {
+            trip: ride.trip,
+            route: graph.gtfs.trips[ride.trip.0].route,
+            stop1: ride.board_stop,
+            stop2: stop,
+            time1: ride.departure,
+            time2: ride.arrival,
+        });
+
+        let r = ready[round - 1][ride.board_stop.0].unwrap();
+        match r.via {
+            None => {
+                legs.push(Leg::Walk {
+                    from: None,
+                    to: Some(ride.board_stop),
+                    time1: start_time,
+                    time2: r.time,
+                });
+                break;
+            }
+            Some(prev) => {
+                if prev != ride.board_stop {
+                    legs.push(Leg::Walk {
+                        from: Some(prev),
+                        to: Some(ride.board_stop),
+                        time1: rides[round - 1][prev.0].unwrap().arrival,
+                        time2: r.time,
+                    });
+                }
+                stop = prev;
+                round -= 1;
+            }
+        }
+    }
+    legs.reverse();
+
+    Journey {
+        departure: start_time,
+        arrival,
+        transfers,
+        legs,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::fixture;
+    use crate::{IntersectionID, RoadID};
+
+    fn position(road: usize) -> Position {
+        Position {
+            road: RoadID(road),
+            fraction_along: 0.5,
+            intersection: IntersectionID(road),
+        }
+    }
+
+    fn options() -> TransitOptions {
+        TransitOptions::new(NaiveDate::from_ymd_opt(2024, 3, 4).unwrap())
+    }
+
+    #[test]
+    fn more_transfers_only_if_faster() {
+        // Walking between A and C takes an hour. The direct trip arrives at 08:40, but changing at
+        // B arrives at 08:25.
+        let graph = fixture::graph("raptor", &[1, 60, 1]);
+        let journeys = graph
+            .transit_journeys(
+                position(0),
+                position(2),
+                &options(),
+                ServiceTime::from_hms(7, 50, 0).unwrap(),
+                3,
+            )
+            .unwrap();
+
+        let summary: Vec<(usize, String, Vec<&str>)> = journeys
+            .iter()
+            .map(|journey| {
+                (
+                    journey.transfers,
+                    journey.arrival.to_string(),
+                    journey
+                        .legs
+                        .iter()
+                        .filter_map(|leg| match leg {
+                            Leg::Transit { trip, .. } => {
+                                Some(graph.gtfs.trips[trip.0].orig_id.as_str())
+                            }
+                            Leg::Walk { .. } => None,
+                        })
+                        .collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (0, "08:40:00".to_string(), vec!["SLOW"]),
+                (1, "08:25:00".to_string(), vec!["FAST1", "FAST2"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn wheelchair_footpaths_use_step_free_access() {
+        let mut graph = fixture::graph("raptor", &[1, 1, 1]);
+        graph.gtfs.stops[1].access[0].step_free = false;
+        let profile = graph.walking_profile_for_transit.unwrap();
+        let nearby = |options: &TransitOptions| -> Vec<StopID> {
+            graph.footpaths(options, profile)[0]
+                .iter()
+                .map(|(stop, _)| *stop)
+                .collect()
+        };
+
+        assert_eq!(nearby(&options()), vec![StopID(1), StopID(2)]);
+        let mut wheelchair = options();
+        wheelchair.wheelchair = true;
+        assert_eq!(nearby(&wheelchair), vec![StopID(2)]);
+    }
+}
@@ -52,6 +52,17 @@ impl Timer {
         self.last_step = Some((step, self.indent, Instant::now()));
     }
 
+    /// Logs how quickly the current step is getting through some large input
+    pub fn progress(&self, count: usize, unit: &str) {
+        if let Some((step, _, start)) = &self.last_step {
+            let seconds = (Instant::now() - *start).as_secs_f64().max(0.001);
+            self.log(format!(
+                "{step}: {count} {unit} ({:.0} {unit}/s)",
+                count as f64 / seconds
+            ));
+        }
+    }
+
     /// Start a new step with nested steps following it
     pub fn push<I: Into<String>>(&mut self, step: I) {
         let step = step.into();
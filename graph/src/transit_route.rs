@@ -3,12 +3,12 @@ use std::collections::{BinaryHeap, HashMap};
 use std::time::Duration;
 
 use anyhow::{Result, bail};
-use chrono::NaiveTime;
 use geo::{Distance, Euclidean, LineString};
 use geojson::{Feature, GeoJson, Geometry};
 use utils::PriorityQueueItem;
 
-use crate::{Graph, IntersectionID, PathStep, Position, Timer};
+use crate::gtfs::{Alighting, FareSoFar};
+use crate::{Graph, IntersectionID, PathStep, Position, ServiceTime, Timer, TransitOptions};
 
 impl Graph {
     pub fn transit_route_gj(
@@ -19,7 +19,8 @@ impl Graph {
         // like a good idea yet
         debug_search: bool,
         use_heuristic: bool,
-        start_time: NaiveTime,
+        options: &TransitOptions,
+        start_time: ServiceTime,
         mut timer: Timer,
     ) -> Result<String> {
         if start == end {
@@ -55,17 +56,18 @@ impl Graph {
         let mut search_record: Vec<IntersectionID> = Vec::new();
 
         timer.step("dijkstra");
-        // Store the actual cost/time to reach somewhere as the item. Include a heuristic
-        let mut queue: BinaryHeap<PriorityQueueItem<NaiveTime, (IntersectionID, NaiveTime)>> =
-            BinaryHeap::new();
+        // Store the actual cost/time to reach somewhere as the item, along with the last time a
+        // trip was used and the fare so far. Include a heuristic
+        type State = (IntersectionID, ServiceTime, Option<Alighting>, FareSoFar);
+        let mut queue: BinaryHeap<PriorityQueueItem<ServiceTime, State>> = BinaryHeap::new();
         queue.push(PriorityQueueItem::new(
             start_time + heuristic(start.intersection),
-            (start.intersection, start_time),
+            (start.intersection, start_time, None, FareSoFar::default()),
         ));
 
         while let Some(current) = queue.pop() {
             // Don't use current.cost, since it might include a heuristic
-            let (current_i, current_time) = current.value;
+            let (current_i, current_time, alighting, fare) = current.value;
             if current_i == end.intersection {
                 if debug_search {
                     return render_debug(search_record, backrefs, self, timer);
@@ -95,7 +97,7 @@ impl Graph {
                         });
                         queue.push(PriorityQueueItem::new(
                             total_cost + heuristic(road.dst_i),
-                            (road.dst_i, total_cost),
+                            (road.dst_i, total_cost, alighting, fare),
                         ));
                     }
                 } else if road.dst_i == current_i && road.allows_backwards(profile) {
@@ -111,38 +113,85 @@ impl Graph {
                         });
                         queue.push(PriorityQueueItem::new(
                             total_cost + heuristic(road.src_i),
-                            (road.src_i, total_cost),
+                            (road.src_i, total_cost, alighting, fare),
                         ));
                     }
                 }
 
                 // Use transit!
                 for stop1 in &road.stops {
+                    let Some(board_time) = self.gtfs.earliest_boarding(
+                        alighting,
+                        *stop1,
+                        |a| {
+                            if a.position.road != *r {
+                                return None;
+                            }
+                            Some(
+                                current_time
+                                    + road.cost_from_end(
+                                        current_i,
+                                        a.position.fraction_along,
+                                        profile,
+                                    )?,
+                            )
+                        },
+                        options,
+                    ) else {
+                        continue;
+                    };
                     // Find all trips leaving from this step in the next 30 minutes
                     // TODO Figure out how to prune that search time better
-                    for next_step in
-                        self.gtfs
-                            .trips_from(*stop1, current_time, Duration::from_secs(30 * 60))
-                    {
-                        // TODO Here's the awkwardness -- arrive at both the intersections for that
-                        // road
-                        let stop2_road = &self.roads[self.gtfs.stops[next_step.stop2.0].road.0];
-                        for i in [stop2_road.src_i, stop2_road.dst_i] {
-                            if let Entry::Vacant(entry) = backrefs.entry(i) {
-                                entry.insert(Backreference {
-                                    src_i: current_i,
-                                    step: PathStep::Transit {
-                                        stop1: *stop1,
-                                        trip: next_step.trip,
-                                        stop2: next_step.stop2,
-                                    },
-                                    time1: next_step.time1,
-                                    time2: next_step.time2,
-                                });
-                                queue.push(PriorityQueueItem::new(
-                                    next_step.time2 + heuristic(i),
-                                    (i, next_step.time2),
-                                ));
+                    for next_step in self.gtfs.trips_from(
+                        *stop1,
+                        board_time,
+                        Duration::from_secs(30 * 60),
+                        options,
+                    ) {
+                        if !self.gtfs.can_board(alighting, *stop1, &next_step, options) {
+                            continue;
+                        }
+                        // Get off here or anywhere later on the same vehicle
+                        let stay_on = self.gtfs.stay_on(&next_step, options);
+                        for step in std::iter::once(next_step).chain(stay_on) {
+                            let (next_fare, total_fare) =
+                                self.gtfs.ride_fare(alighting, fare, *stop1, &step);
+                            if options.max_fare.is_some_and(|max| total_fare > max) {
+                                continue;
+                            }
+                            let arrival = Some(Alighting {
+                                stop: step.stop2,
+                                trip: step.trip,
+                                time: step.time2,
+                            });
+                            // Walk from the stop to both ends of its road
+                            for access in self.gtfs.stops[step.stop2.0].usable_access(options) {
+                                let pos = access.position;
+                                let stop2_road = &self.roads[pos.road.0];
+                                for i in [stop2_road.src_i, stop2_road.dst_i] {
+                                    let Some(walk) =
+                                        stop2_road.cost_to_end(pos.fraction_along, i, profile)
+                                    else {
+                                        continue;
+                                    };
+                                    let time = step.time2 + access.exit + walk;
+                                    if let Entry::Vacant(entry) = backrefs.entry(i) {
+                                        entry.insert(Backreference {
+                                            src_i: current_i,
+                                            step: PathStep::Transit {
+                                                stop1: *stop1,
+                                                trip: step.trip,
+                                                stop2: step.stop2,
+                                            },
+                                            time1: step.time1,
+                                            time2: step.time2,
+                                        });
+                                        queue.push(PriorityQueueItem::new(
+                                            time + heuristic(i),
+                                            (i, time, arrival, next_fare),
+                                        ));
+                                    }
+                                }
                             }
                         }
                     }
@@ -160,9 +209,9 @@ struct Backreference {
     src_i: IntersectionID,
     step: PathStep,
     // When'd we start this step?
-    time1: NaiveTime,
+    time1: ServiceTime,
     // When'd we finish?
-    time2: NaiveTime,
+    time2: ServiceTime,
 }
 
 fn render_path(
@@ -175,7 +224,7 @@ fn render_path(
     timer.step("render");
 
     // Just get PathSteps in order first (Step, time1, time2)
-    let mut steps: Vec<(PathStep, NaiveTime, NaiveTime)> = Vec::new();
+    let mut steps: Vec<(PathStep, ServiceTime, ServiceTime)> = Vec::new();
     let mut at = end.intersection;
     loop {
         if at == start.intersection {
@@ -199,6 +248,8 @@ fn render_path(
         let mut pts = Vec::new();
         let mut num_stops = 0;
         let mut trip_id = None;
+        let mut first_stop = None;
+        let mut last_stop = None;
         for (step, _, _) in chunk {
             match step {
                 PathStep::Road { road, forwards } => {
@@ -213,9 +264,19 @@ fn render_path(
                 }
                 PathStep::Transit { stop1, stop2, trip } => {
                     trip_id = Some(trip);
-                    num_stops += 1;
-                    pts.push(graph.gtfs.stops[stop1.0].point.into());
-                    pts.push(graph.gtfs.stops[stop2.0].point.into());
+                    // One step may pass through several stops
+                    num_stops += graph
+                        .gtfs
+                        .ride_indices(*trip, *stop1, *stop2)
+                        .map_or(1, |(idx1, idx2)| idx2 - idx1);
+                    first_stop.get_or_insert(*stop1);
+                    last_stop = Some(*stop2);
+                    if let Some(geometry) = graph.gtfs.trip_geometry(*trip, *stop1, *stop2) {
+                        pts.extend(geometry);
+                    } else {
+                        pts.push(graph.gtfs.stops[stop1.0].point.into());
+                        pts.push(graph.gtfs.stops[stop2.0].point.into());
+                    }
                 }
             }
         }
@@ -228,13 +289,24 @@ fn render_path(
         f.set_property("time2", chunk.last().unwrap().2.to_string());
 
         if let Some(trip) = trip_id {
+            let route = &graph.gtfs.routes[graph.gtfs.trips[trip.0].route.0];
             f.set_property("kind", "transit");
             f.set_property("trip", trip.0);
-            f.set_property(
-                "route",
-                graph.gtfs.routes[graph.gtfs.trips[trip.0].route.0].describe(),
-            );
+            f.set_property("route", route.describe());
+            f.set_property("mode", serde_json::to_value(route.mode())?);
             f.set_property("num_stops", num_stops);
+            match graph
+                .gtfs
+                .leg_fare(*trip, first_stop.unwrap(), last_stop.unwrap())
+            {
+                Some(rule) => {
+                    f.set_property("fare", rule.price);
+                    f.set_property("currency", rule.currency.clone());
+                }
+                None => {
+                    f.set_property("fare", serde_json::Value::Null);
+                }
+            }
         } else {
             f.set_property("kind", "road");
         }
@@ -16,21 +16,31 @@ export class Backend {
     this.inner = null;
   }
 
+  // gtfs is either a URL to a geomedea file or the bytes of a zipped GTFS feed
   async loadOsmFile(
     osmBytes: Uint8Array,
-    gtfsUrl: string | undefined,
+    gtfs: string | Uint8Array | undefined,
     populationUrl: string | undefined,
     progressCb: (msg: string) => void,
   ) {
     // TODO Do we need to do this only once?
     await init();
 
-    this.inner = await new MapModel(
-      osmBytes,
-      gtfsUrl,
-      populationUrl,
-      progressCb,
-    );
+    if (gtfs instanceof Uint8Array) {
+      this.inner = await MapModel.withGtfsBytes(
+        osmBytes,
+        gtfs,
+        populationUrl,
+        progressCb,
+      );
+    } else {
+      this.inner = await new MapModel(
+        osmBytes,
+        gtfs,
+        populationUrl,
+        progressCb,
+      );
+    }
   }
 
   async loadModelFile(graphBytes: Uint8Array) {